@@ -0,0 +1,8 @@
+create table gmo.assets (
+  timestamp       TIMESTAMP  NOT NULL,
+  symbol          STRING     NOT NULL,
+  amount          NUMERIC    NOT NULL,
+  available       NUMERIC    NOT NULL,
+  conversion_rate NUMERIC    NOT NULL,
+  jpy_value       BIGNUMERIC NOT NULL
+)
@@ -0,0 +1,12 @@
+create table gmo.candles (
+  symbol     STRING    NOT NULL,
+  `interval` STRING    NOT NULL,
+  open_time  TIMESTAMP NOT NULL,
+  open       NUMERIC   NOT NULL,
+  high       NUMERIC   NOT NULL,
+  low        NUMERIC   NOT NULL,
+  close      NUMERIC   NOT NULL,
+  volume     NUMERIC   NOT NULL,
+  vwap       NUMERIC   NOT NULL,
+  trades     INT64     NOT NULL
+)
@@ -0,0 +1,10 @@
+create table gmo.klines (
+  symbol     STRING    NOT NULL,
+  `interval` STRING    NOT NULL,
+  open_time  TIMESTAMP NOT NULL,
+  open       NUMERIC   NOT NULL,
+  high       NUMERIC   NOT NULL,
+  low        NUMERIC   NOT NULL,
+  close      NUMERIC   NOT NULL,
+  volume     NUMERIC   NOT NULL
+)
@@ -0,0 +1,7 @@
+create table gmo.market_trades (
+  symbol    STRING    NOT NULL,
+  side      STRING    NOT NULL,
+  price     NUMERIC   NOT NULL,
+  size      NUMERIC   NOT NULL,
+  timestamp TIMESTAMP NOT NULL
+)
@@ -0,0 +1,12 @@
+create table gmo.orderbook_metrics (
+  timestamp     TIMESTAMP NOT NULL,
+  symbol        STRING    NOT NULL,
+  best_bid      NUMERIC   NOT NULL,
+  best_ask      NUMERIC   NOT NULL,
+  spread        NUMERIC   NOT NULL,
+  mid_price     NUMERIC   NOT NULL,
+  bid_depth_0_5 NUMERIC   NOT NULL,
+  ask_depth_0_5 NUMERIC   NOT NULL,
+  bid_depth_1   NUMERIC   NOT NULL,
+  ask_depth_1   NUMERIC   NOT NULL
+)
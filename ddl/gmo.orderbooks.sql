@@ -0,0 +1,8 @@
+create table gmo.orderbooks (
+  timestamp TIMESTAMP NOT NULL,
+  symbol    STRING    NOT NULL,
+  side      STRING    NOT NULL,
+  level     INTEGER   NOT NULL,
+  price     NUMERIC   NOT NULL,
+  size      NUMERIC   NOT NULL
+)
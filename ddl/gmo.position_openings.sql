@@ -0,0 +1,8 @@
+create table gmo.position_openings (
+  timestamp  TIMESTAMP NOT NULL,
+  method     STRING    NOT NULL,
+  symbol     STRING    NOT NULL,
+  size       NUMERIC   NOT NULL,
+  price      NUMERIC   NOT NULL,
+  rebuilt_at TIMESTAMP NOT NULL
+)
@@ -0,0 +1,9 @@
+create table gmo.positions (
+  timestamp     TIMESTAMP  NOT NULL,
+  execution_id  INTEGER    NOT NULL,
+  symbol        STRING     NOT NULL,
+  method        STRING     NOT NULL,
+  average_price BIGNUMERIC NOT NULL,
+  size          NUMERIC    NOT NULL,
+  cost          BIGNUMERIC NOT NULL
+)
@@ -0,0 +1,14 @@
+create table gmo.realized_pnl (
+  timestamp    TIMESTAMP  NOT NULL,
+  execution_id INTEGER    NOT NULL,
+  symbol       STRING     NOT NULL,
+  method       STRING     NOT NULL,
+  size         NUMERIC    NOT NULL,
+  price        NUMERIC    NOT NULL,
+  proceeds     NUMERIC    NOT NULL,
+  cost_basis   BIGNUMERIC NOT NULL,
+  fee          NUMERIC    NOT NULL,
+  realized_pnl BIGNUMERIC NOT NULL,
+  loss_gain    NUMERIC    NOT NULL,
+  discrepancy  BOOL       NOT NULL
+)
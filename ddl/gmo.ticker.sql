@@ -1,5 +1,10 @@
 create table gmo.ticker( 
-  timestamp DATETIME NOT NULL,
-  symbol    STRING   NOT NULL,
-  last      NUMERIC  NOT NULL
+  timestamp TIMESTAMP NOT NULL,
+  symbol    STRING    NOT NULL,
+  ask       NUMERIC   NOT NULL,
+  bid       NUMERIC   NOT NULL,
+  high      NUMERIC   NOT NULL,
+  low       NUMERIC   NOT NULL,
+  last      NUMERIC   NOT NULL,
+  volume    NUMERIC   NOT NULL
 )
@@ -0,0 +1,10 @@
+create table gmo.transfers (
+  transaction_id STRING    NOT NULL,
+  transfer_type  STRING    NOT NULL,
+  symbol         STRING    NOT NULL,
+  amount         NUMERIC   NOT NULL,
+  fee            NUMERIC   NOT NULL,
+  status         STRING    NOT NULL,
+  address        STRING,
+  timestamp      TIMESTAMP NOT NULL
+)
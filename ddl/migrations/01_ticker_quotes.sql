@@ -0,0 +1,8 @@
+-- Add the quotes of the full ticker to a ticker table created before they were stored. Columns
+-- cannot be added as NOT NULL, and the rows saved before have no quotes.
+alter table gmo.ticker
+  add column if not exists ask    NUMERIC,
+  add column if not exists bid    NUMERIC,
+  add column if not exists high   NUMERIC,
+  add column if not exists low    NUMERIC,
+  add column if not exists volume NUMERIC;
@@ -0,0 +1,5 @@
+-- Add the JPY valuation to an assets table created before it was stored. Columns cannot be added
+-- as NOT NULL, and the rows saved before have no valuation.
+alter table gmo.assets
+  add column if not exists conversion_rate NUMERIC,
+  add column if not exists jpy_value       BIGNUMERIC;
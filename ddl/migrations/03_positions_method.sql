@@ -0,0 +1,11 @@
+-- Add the symbol and the cost method to a positions table created before they were stored.
+-- Columns cannot be added as NOT NULL. The rows saved before averaged the executions of every
+-- symbol together, so they are deleted rather than backfilled, and the next average_price run
+-- recalculates them. Run it when positions has not been written for the last 90 minutes, since
+-- rows in the streaming buffer cannot be deleted.
+alter table gmo.positions
+  add column if not exists symbol STRING,
+  add column if not exists method STRING,
+  add column if not exists cost   BIGNUMERIC;
+
+delete from gmo.positions where method is null;
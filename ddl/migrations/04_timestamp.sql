@@ -0,0 +1,36 @@
+-- Convert the times of tables created with DATETIME into TIMESTAMP. The times were stored in UTC,
+-- which TIMESTAMP() assumes. Tables are recreated by the queries, so run them when nothing is
+-- being written, and note that the columns are no longer NOT NULL after that. Statements for
+-- tables which already have TIMESTAMP fail and can be skipped.
+create or replace table gmo.my_executions as
+select * replace(timestamp(timestamp) as timestamp) from gmo.my_executions;
+
+create or replace table gmo.assets as
+select * replace(timestamp(timestamp) as timestamp) from gmo.assets;
+
+create or replace table gmo.ticker as
+select * replace(timestamp(timestamp) as timestamp) from gmo.ticker;
+
+create or replace table gmo.positions as
+select * replace(timestamp(timestamp) as timestamp) from gmo.positions;
+
+create or replace table gmo.realized_pnl as
+select * replace(timestamp(timestamp) as timestamp) from gmo.realized_pnl;
+
+create or replace table gmo.transfers as
+select * replace(timestamp(timestamp) as timestamp) from gmo.transfers;
+
+create or replace table gmo.orderbooks as
+select * replace(timestamp(timestamp) as timestamp) from gmo.orderbooks;
+
+create or replace table gmo.orderbook_metrics as
+select * replace(timestamp(timestamp) as timestamp) from gmo.orderbook_metrics;
+
+create or replace table gmo.market_trades as
+select * replace(timestamp(timestamp) as timestamp) from gmo.market_trades;
+
+create or replace table gmo.klines as
+select * replace(timestamp(open_time) as open_time) from gmo.klines;
+
+create or replace table gmo.candles as
+select * replace(timestamp(open_time) as open_time) from gmo.candles;
@@ -1,37 +1,164 @@
+use crate::config::Config;
 use gcp_bigquery_client::{
-    model::table_data_insert_all_request::TableDataInsertAllRequest, Client,
+    error::BQError,
+    model::{
+        get_query_results_parameters::GetQueryResultsParameters,
+        query_request::QueryRequest,
+        query_response::{QueryResponse, ResultSet},
+        table_data_insert_all_request::TableDataInsertAllRequest,
+        table_data_insert_all_response::TableDataInsertAllResponse,
+    },
+    Client,
 };
-use std::env;
+use serde::Serialize;
+
+// Number of rows inserted by one request in bulk insertion.
+const INSERT_CHUNK_SIZE: usize = 500;
+
+// Time to wait for a query job in one request.
+const QUERY_TIMEOUT_MS: i32 = 30_000;
 
 // create BigQuery client
-pub async fn create_bq_client() -> Client {
-    let key_str = env::var("SERVICE_ACCOUNT_KEY").unwrap();
+pub async fn create_bq_client(config: &Config) -> Client {
+    let key_str = config.service_account_key();
     let key = serde_json::from_str(&key_str).unwrap();
     Client::from_service_account_key(key, false).await.unwrap()
 }
 
-pub async fn insert_bq(ins_req: TableDataInsertAllRequest, dataset_id: &str, table_id: &str) {
+// Insert rows into the table. Returns false when the insertion failed.
+pub async fn insert_bq(
+    config: &Config,
+    ins_req: TableDataInsertAllRequest,
+    table_id: &str,
+) -> bool {
     // create BigQuery client
-    let bq_client = create_bq_client().await;
+    let bq_client = create_bq_client(config).await;
+    insert_all(&bq_client, config, ins_req, table_id).await
+}
+
+// Insert many rows into the table in chunks with one client. Returns false when any insertion
+// failed.
+pub async fn insert_bq_rows<T: Serialize>(config: &Config, rows: &[T], table_id: &str) -> bool {
+    if rows.is_empty() {
+        println!("There is no new record.");
+        return true;
+    }
+    let bq_client = create_bq_client(config).await;
+    insert_bq_rows_with(&bq_client, config, rows, table_id).await
+}
+
+// Insert many rows into the table in chunks with the client. Returns false when any insertion
+// failed.
+pub async fn insert_bq_rows_with<T: Serialize>(
+    bq_client: &Client,
+    config: &Config,
+    rows: &[T],
+    table_id: &str,
+) -> bool {
+    let mut succeeded = true;
+    for chunk in rows.chunks(INSERT_CHUNK_SIZE) {
+        let mut ins_req = TableDataInsertAllRequest::new();
+        for row in chunk {
+            ins_req.add_row(None, row).unwrap();
+        }
+        succeeded &= insert_all(bq_client, config, ins_req, table_id).await;
+    }
+    succeeded
+}
 
+async fn insert_all(
+    bq_client: &Client,
+    config: &Config,
+    ins_req: TableDataInsertAllRequest,
+    table_id: &str,
+) -> bool {
     // add new executions to table
     let row_num = ins_req.len();
     if row_num > 0 {
-        let project_id = &env::var("BQ_PROJECT_ID").unwrap();
         let res = bq_client
             .tabledata()
-            .insert_all(project_id, dataset_id, table_id, ins_req)
+            .insert_all(config.project_id(), config.dataset_id(), table_id, ins_req)
             .await;
 
         match res {
+            // rows can be rejected one by one while the request succeeds
+            Ok(TableDataInsertAllResponse {
+                insert_errors: Some(errors),
+                ..
+            }) if !errors.is_empty() => {
+                println!(
+                    "Failed to add {} of {} records => {:?}",
+                    errors.len(),
+                    row_num,
+                    errors.first()
+                );
+                false
+            }
             Ok(_) => {
                 println!("Suceeded to add new {} records.", row_num);
+                true
             }
             Err(e) => {
                 println!("Failed to add new records => {:?}", e);
+                false
             }
         }
     } else {
         println!("There is no new record.");
+        true
+    }
+}
+
+// Run the query and return every page of the result. The query API returns only the first page,
+// and no rows at all when the job does not complete in time, so wait for the job and follow the
+// page tokens.
+pub async fn query_all(
+    client: &Client,
+    project_id: &str,
+    query: String,
+) -> Result<Vec<ResultSet>, BQError> {
+    let mut request = QueryRequest::new(query);
+    request.timeout_ms = Some(QUERY_TIMEOUT_MS);
+    let first = client.job().query(project_id, request).await?;
+    let mut response = first.query_response().clone();
+
+    let job_reference = response.job_reference.clone().unwrap_or_default();
+    let mut pages = vec![];
+    let mut page_token: Option<String> = None;
+    loop {
+        if response.job_complete == Some(true) {
+            page_token = response.page_token.clone();
+            pages.push(ResultSet::new(response));
+            if page_token.is_none() {
+                break;
+            }
+        }
+        let job_id = job_reference.job_id.as_deref().unwrap_or_default();
+        response = QueryResponse::from(
+            client
+                .job()
+                .get_query_results(
+                    project_id,
+                    job_id,
+                    GetQueryResultsParameters {
+                        location: job_reference.location.clone(),
+                        page_token: page_token.clone(),
+                        timeout_ms: Some(QUERY_TIMEOUT_MS),
+                        ..Default::default()
+                    },
+                )
+                .await?,
+        );
     }
+    Ok(pages)
+}
+
+// Run the DML statement and return the number of affected rows.
+pub async fn execute_dml(client: &Client, project_id: &str, query: String) -> Result<i64, BQError> {
+    let pages = query_all(client, project_id, query).await?;
+    Ok(pages
+        .iter()
+        .filter_map(|rs| rs.query_response().num_dml_affected_rows.as_deref())
+        .filter_map(|v| v.parse::<i64>().ok())
+        .sum())
 }
@@ -0,0 +1,466 @@
+use {
+    crate::{gmo::KLINE_INTERVALS, tax::EXPORT_FIELDS},
+    chrono::{DateTime, NaiveDate, Utc},
+    chrono_tz::Tz,
+    cron::Schedule,
+    serde::Deserialize,
+    std::{collections::BTreeMap, env, fs, path::Path, str::FromStr, time::Duration},
+};
+
+// Configuration file read when no path is given.
+pub const DEFAULT_CONFIG_PATH: &str = "gmo.toml";
+
+// Jobs with their default settings, which follow the GitHub Actions workflows. Empty symbols mean
+// every symbol. average_price and aggregate run a little later so that they see the executions
+// and trades saved by my_executions and trades. Only the jobs run by the workflows are enabled
+// unless configured.
+const JOB_DEFAULTS: [JobDefault; 9] = [
+    JobDefault {
+        name: "assets",
+        enabled: true,
+        symbols: &[],
+        intervals: &[],
+        schedule: "0 */6 * * *",
+    },
+    JobDefault {
+        name: "ticker",
+        enabled: true,
+        symbols: &[],
+        intervals: &[],
+        schedule: "0 */6 * * *",
+    },
+    JobDefault {
+        name: "my_executions",
+        enabled: true,
+        symbols: &["BTC"],
+        intervals: &[],
+        schedule: "0 */6 * * *",
+    },
+    JobDefault {
+        name: "average_price",
+        enabled: true,
+        symbols: &[],
+        intervals: &[],
+        schedule: "10 */6 * * *",
+    },
+    JobDefault {
+        name: "transfers",
+        enabled: false,
+        symbols: &[],
+        intervals: &[],
+        schedule: "0 */6 * * *",
+    },
+    JobDefault {
+        name: "klines",
+        enabled: false,
+        symbols: &["BTC"],
+        intervals: &["1min", "1hour", "1day"],
+        schedule: "0 */6 * * *",
+    },
+    JobDefault {
+        name: "orderbook",
+        enabled: false,
+        symbols: &["BTC"],
+        intervals: &[],
+        schedule: "*/10 * * * *",
+    },
+    JobDefault {
+        name: "trades",
+        enabled: false,
+        symbols: &["BTC"],
+        intervals: &[],
+        schedule: "*/30 * * * *",
+    },
+    JobDefault {
+        name: "aggregate",
+        enabled: false,
+        symbols: &["BTC"],
+        intervals: &["1min"],
+        schedule: "5,35 * * * *",
+    },
+];
+
+struct JobDefault {
+    name: &'static str,
+    enabled: bool,
+    symbols: &'static [&'static str],
+    intervals: &'static [&'static str],
+    schedule: &'static str,
+}
+
+// Default time limit of one job run in seconds.
+const DEFAULT_JOB_TIMEOUT: u64 = 10 * 60;
+
+// Default number of order book levels to be saved on each side.
+const DEFAULT_LEVELS: usize = 10;
+
+// Configuration of the collector, read from a TOML file like:
+//
+//   [gmo]
+//   api_key = "..."
+//   api_secret = "..."
+//   requests_per_second = 5
+//
+//   [bigquery]
+//   project_id = "..."
+//   dataset_id = "gmo"
+//   service_account_key_path = "key.json"
+//
+//   [jobs.assets]
+//   symbols = ["JPY", "BTC", "ETH"]
+//   schedule = "0 */6 * * *"
+//   timeout = 600
+//
+//   [jobs.transfers]
+//   enabled = true
+//   start = "2021-04-01"
+//
+//   [export]
+//   columns = [{ header = "Date", field = "timestamp" }, { header = "Type", field = "side" }]
+//
+// Environment variables (also read from .env) take precedence over the file: API_KEY, API_SECRET,
+// GMO_REQUESTS_PER_SECOND, BQ_PROJECT_ID, BQ_DATASET_ID, SERVICE_ACCOUNT_KEY, and
+// DAEMON_<JOB>_ENABLED, DAEMON_<JOB>_SCHEDULE and DAEMON_<JOB>_TIMEOUT for each job.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub gmo: GmoConfig,
+    pub bigquery: BigQueryConfig,
+    pub export: ExportConfig,
+    jobs: BTreeMap<String, JobConfig>,
+    // Time zone to display times in, given by --tz.
+    #[serde(skip)]
+    pub tz: Tz,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GmoConfig {
+    pub api_key: Option<String>,
+    pub api_secret: Option<String>,
+    // Maximum number of REST API requests per second.
+    pub requests_per_second: u32,
+}
+
+impl Default for GmoConfig {
+    fn default() -> Self {
+        GmoConfig {
+            api_key: None,
+            api_secret: None,
+            requests_per_second: 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BigQueryConfig {
+    pub project_id: Option<String>,
+    pub dataset_id: String,
+    // JSON of the service account key, or the path of its file.
+    pub service_account_key: Option<String>,
+    pub service_account_key_path: Option<String>,
+}
+
+impl Default for BigQueryConfig {
+    fn default() -> Self {
+        BigQueryConfig {
+            project_id: None,
+            dataset_id: String::from("gmo"),
+            service_account_key: None,
+            service_account_key_path: None,
+        }
+    }
+}
+
+// Layout of the generic format of export-tax-csv. Without columns, every field is written with
+// its name as the header.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    // strftime format of the timestamp in JST
+    pub timestamp_format: String,
+    pub columns: Vec<ExportColumn>,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            timestamp_format: String::from("%Y-%m-%d %H:%M:%S"),
+            columns: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportColumn {
+    pub header: String,
+    pub field: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct JobConfig {
+    enabled: Option<bool>,
+    symbols: Option<Vec<String>>,
+    intervals: Option<Vec<String>>,
+    levels: Option<usize>,
+    start: Option<String>,
+    schedule: Option<String>,
+    timeout: Option<u64>,
+}
+
+// Settings of a job with defaults applied.
+#[derive(Debug)]
+pub struct Job {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub symbols: Vec<String>,
+    pub intervals: Vec<String>,
+    pub levels: usize,
+    // Date (JST) from which the history is collected when nothing is stored, for transfers.
+    pub start: Option<NaiveDate>,
+    pub schedule: String,
+    pub timeout: Duration,
+}
+
+impl Config {
+    // Read the configuration file and apply environment variables. The default file is optional,
+    // while an explicitly given one must exist.
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let mut config = match path {
+            Some(p) => Config::read(p)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::read(DEFAULT_CONFIG_PATH)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    // Time in the display time zone. Times are stored in UTC.
+    pub fn local_time(&self, t: &DateTime<Utc>) -> DateTime<Tz> {
+        t.with_timezone(&self.tz)
+    }
+
+    fn read(path: &str) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), String> {
+        if let Ok(v) = env::var("API_KEY") {
+            self.gmo.api_key = Some(v);
+        }
+        if let Ok(v) = env::var("API_SECRET") {
+            self.gmo.api_secret = Some(v);
+        }
+        if let Ok(v) = env::var("GMO_REQUESTS_PER_SECOND") {
+            self.gmo.requests_per_second = v
+                .parse()
+                .map_err(|e| format!("GMO_REQUESTS_PER_SECOND: {}", e))?;
+        }
+        if let Ok(v) = env::var("BQ_PROJECT_ID") {
+            self.bigquery.project_id = Some(v);
+        }
+        if let Ok(v) = env::var("BQ_DATASET_ID") {
+            self.bigquery.dataset_id = v;
+        }
+        if let Ok(v) = env::var("SERVICE_ACCOUNT_KEY") {
+            self.bigquery.service_account_key = Some(v);
+        }
+        for JobDefault { name, .. } in JOB_DEFAULTS {
+            let prefix = format!("DAEMON_{}", name.to_uppercase());
+            if let Ok(v) = env::var(format!("{}_ENABLED", prefix)) {
+                let enabled = v
+                    .parse()
+                    .map_err(|e| format!("{}_ENABLED: {}", prefix, e))?;
+                self.jobs.entry(name.to_string()).or_default().enabled = Some(enabled);
+            }
+            if let Ok(v) = env::var(format!("{}_SCHEDULE", prefix)) {
+                self.jobs.entry(name.to_string()).or_default().schedule = Some(v);
+            }
+            if let Ok(v) = env::var(format!("{}_TIMEOUT", prefix)) {
+                let timeout = v
+                    .parse()
+                    .map_err(|e| format!("{}_TIMEOUT: {}", prefix, e))?;
+                self.jobs.entry(name.to_string()).or_default().timeout = Some(timeout);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn api_key(&self) -> &str {
+        self.gmo
+            .api_key
+            .as_deref()
+            .expect("API key is not configured")
+    }
+
+    pub fn api_secret(&self) -> &str {
+        self.gmo
+            .api_secret
+            .as_deref()
+            .expect("API secret is not configured")
+    }
+
+    pub fn project_id(&self) -> &str {
+        self.bigquery
+            .project_id
+            .as_deref()
+            .expect("BigQuery project ID is not configured")
+    }
+
+    pub fn dataset_id(&self) -> &str {
+        &self.bigquery.dataset_id
+    }
+
+    // Fully qualified name of the table to be used in queries.
+    pub fn table(&self, table_id: &str) -> String {
+        format!("{}.{}.{}", self.project_id(), self.dataset_id(), table_id)
+    }
+
+    pub fn service_account_key(&self) -> String {
+        self.read_service_account_key().unwrap()
+    }
+
+    fn read_service_account_key(&self) -> Result<String, String> {
+        match (
+            &self.bigquery.service_account_key,
+            &self.bigquery.service_account_key_path,
+        ) {
+            (Some(key), _) => Ok(key.clone()),
+            (None, Some(path)) => fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e)),
+            (None, None) => Err(String::from("service account key is not configured")),
+        }
+    }
+
+    pub fn job(&self, name: &str) -> Job {
+        let default = JOB_DEFAULTS
+            .iter()
+            .find(|d| d.name == name)
+            .unwrap_or_else(|| panic!("Unknown job: {}", name));
+        let conf = self.jobs.get(name);
+        Job {
+            name: default.name,
+            enabled: conf.and_then(|c| c.enabled).unwrap_or(default.enabled),
+            symbols: conf
+                .and_then(|c| c.symbols.clone())
+                .unwrap_or_else(|| to_strings(default.symbols)),
+            intervals: conf
+                .and_then(|c| c.intervals.clone())
+                .unwrap_or_else(|| to_strings(default.intervals)),
+            levels: conf.and_then(|c| c.levels).unwrap_or(DEFAULT_LEVELS),
+            start: conf.and_then(|c| c.start.as_deref()).map(|v| {
+                parse_date(v).unwrap_or_else(|e| panic!("jobs.{}.start is invalid: {}", name, e))
+            }),
+            schedule: conf
+                .and_then(|c| c.schedule.clone())
+                .unwrap_or_else(|| default.schedule.to_string()),
+            timeout: Duration::from_secs(
+                conf.and_then(|c| c.timeout).unwrap_or(DEFAULT_JOB_TIMEOUT),
+            ),
+        }
+    }
+
+    pub fn jobs(&self) -> Vec<Job> {
+        JOB_DEFAULTS.iter().map(|d| self.job(d.name)).collect()
+    }
+
+    // Validate the configuration and return the problems found.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if is_blank(&self.gmo.api_key) {
+            problems.push(String::from("gmo.api_key (API_KEY) is missing"));
+        }
+        if is_blank(&self.gmo.api_secret) {
+            problems.push(String::from("gmo.api_secret (API_SECRET) is missing"));
+        }
+        if self.gmo.requests_per_second == 0 {
+            problems.push(String::from("gmo.requests_per_second must be positive"));
+        }
+        if is_blank(&self.bigquery.project_id) {
+            problems.push(String::from(
+                "bigquery.project_id (BQ_PROJECT_ID) is missing",
+            ));
+        }
+        match self.read_service_account_key() {
+            Ok(key) => {
+                if let Err(e) = serde_json::from_str::<yup_oauth2::ServiceAccountKey>(&key) {
+                    problems.push(format!("service account key is invalid: {}", e));
+                }
+            }
+            Err(e) => problems.push(format!(
+                "{} (bigquery.service_account_key, bigquery.service_account_key_path or SERVICE_ACCOUNT_KEY)",
+                e
+            )),
+        }
+        for name in self.jobs.keys() {
+            if !JOB_DEFAULTS.iter().any(|d| d.name == name) {
+                problems.push(format!("jobs.{} is not a known job", name));
+            }
+        }
+        for (name, conf) in &self.jobs {
+            if let Some(Err(e)) = conf.start.as_deref().map(parse_date) {
+                problems.push(format!("jobs.{}.start is invalid: {}", name, e));
+            }
+        }
+        for job in self.jobs() {
+            if let Err(e) = parse_schedule(&job.schedule) {
+                problems.push(format!("jobs.{}.schedule is invalid: {}", job.name, e));
+            }
+        }
+        for interval in self.job("klines").intervals {
+            if !KLINE_INTERVALS.contains(&interval.as_str()) {
+                problems.push(format!("jobs.klines.intervals has unknown {}", interval));
+            }
+        }
+        for interval in self.job("aggregate").intervals {
+            if parse_interval(&interval).is_none() {
+                problems.push(format!("jobs.aggregate.intervals has invalid {}", interval));
+            }
+        }
+        for column in &self.export.columns {
+            if !EXPORT_FIELDS.contains(&column.field.as_str()) {
+                problems.push(format!("export.columns has unknown field {}", column.field));
+            }
+        }
+        problems
+    }
+}
+
+fn to_strings(v: &[&str]) -> Vec<String> {
+    v.iter().map(|s| s.to_string()).collect()
+}
+
+fn is_blank(v: &Option<String>) -> bool {
+    v.as_deref().unwrap_or("").is_empty()
+}
+
+fn parse_date(v: &str) -> Result<NaiveDate, chrono::ParseError> {
+    NaiveDate::parse_from_str(v, "%Y-%m-%d")
+}
+
+// Parse a cron expression. The standard five fields expression is also accepted, which is
+// completed with the seconds field.
+pub fn parse_schedule(expr: &str) -> Result<Schedule, cron::error::Error> {
+    let expr = match expr.split_whitespace().count() {
+        5 => format!("0 {}", expr),
+        _ => expr.to_string(),
+    };
+    Schedule::from_str(&expr)
+}
+
+// Parse an interval like "3s", "10min", "4hour" or "1day" into seconds.
+pub fn parse_interval(v: &str) -> Option<i64> {
+    let unit_at = v.find(|c: char| !c.is_ascii_digit())?;
+    let n = v[..unit_at].parse::<i64>().ok().filter(|n| *n > 0)?;
+    let unit = match &v[unit_at..] {
+        "s" | "sec" => 1,
+        "m" | "min" => 60,
+        "h" | "hour" => 60 * 60,
+        "d" | "day" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(n * unit)
+}
@@ -0,0 +1,116 @@
+use {
+    crate::{
+        config::{parse_schedule, Config, Job},
+        position::CostMethod,
+        subcommands,
+    },
+    chrono::Utc,
+    std::{process, sync::Arc},
+    tokio::{
+        signal::unix::{signal, SignalKind},
+        sync::watch,
+        time::{sleep, timeout},
+    },
+};
+
+// Run the enabled jobs on the schedules in the configuration until SIGTERM or Ctrl-C is received.
+pub async fn run(config: Config) {
+    // refuse to start rather than failing in every job run
+    let problems = config.check();
+    if !problems.is_empty() {
+        for p in problems {
+            println!("Config error: {}", p);
+        }
+        process::exit(1);
+    }
+
+    let config = Arc::new(config);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let mut handles = vec![];
+    for job in config.jobs() {
+        if !job.enabled {
+            println!("Job {}: disabled", job.name);
+            continue;
+        }
+        println!(
+            "Job {}: schedule='{}', timeout={:?}",
+            job.name, job.schedule, job.timeout
+        );
+        handles.push(tokio::spawn(schedule_job(
+            config.clone(),
+            job,
+            shutdown_rx.clone(),
+        )));
+    }
+
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = sigterm.recv() => println!("Received SIGTERM."),
+        _ = tokio::signal::ctrl_c() => println!("Received Ctrl-C."),
+    }
+
+    // stop scheduling and wait for the running jobs to finish
+    println!("Shutting down...");
+    shutdown_tx.send(true).ok();
+    for handle in handles {
+        handle.await.ok();
+    }
+    println!("Stopped.");
+}
+
+// Run the job each time it comes due. A run is never started while the previous one is still
+// running; such runs are skipped.
+async fn schedule_job(config: Arc<Config>, job: Job, mut shutdown: watch::Receiver<bool>) {
+    let schedule = parse_schedule(&job.schedule).unwrap();
+    loop {
+        let next = schedule.upcoming(Utc).next().unwrap();
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = sleep(wait) => {}
+            _ = shutdown.changed() => return,
+        }
+        if *shutdown.borrow() {
+            return;
+        }
+
+        println!("Start job {}.", job.name);
+        let c = config.clone();
+        let name = job.name;
+        let mut handle = tokio::spawn(async move { run_job(&c, name).await });
+        match timeout(job.timeout, &mut handle).await {
+            Ok(Ok(_)) => println!("Finished job {}.", job.name),
+            Ok(Err(e)) => println!("Failed job {} => {:?}", job.name, e),
+            Err(_) => {
+                handle.abort();
+                println!("Timed out job {} after {:?}.", job.name, job.timeout);
+            }
+        }
+
+        let missed = schedule
+            .after(&next)
+            .take_while(|t| *t < Utc::now())
+            .count();
+        if missed > 0 {
+            println!(
+                "Skipped {} runs of job {} while it was running.",
+                missed, job.name
+            );
+        }
+    }
+}
+
+async fn run_job(config: &Config, name: &str) {
+    match name {
+        "assets" => subcommands::get_assets(config).await,
+        "ticker" => subcommands::get_ticker(config).await,
+        "my_executions" => subcommands::get_my_executions(config).await,
+        "average_price" => subcommands::get_avg_price(config, CostMethod::Moving).await,
+        "transfers" => subcommands::get_transfers(config).await,
+        "klines" => subcommands::get_klines(config, None, None).await,
+        "orderbook" => subcommands::get_orderbooks(config).await,
+        "trades" => subcommands::get_trades(config).await,
+        "aggregate" => subcommands::aggregate(config, None, None).await,
+        _ => println!("Unknown job: {}", name),
+    }
+}
@@ -1,23 +1,140 @@
 use {
-    chrono::Utc,
+    chrono::{DateTime, NaiveDate, SecondsFormat, Utc},
     hex::encode,
     reqwest::{
         header::{HeaderMap, HeaderValue},
-        Client,
+        Client, StatusCode,
     },
     ring::hmac::{sign, Key, HMAC_SHA256},
     serde::{Deserialize, Serialize},
-    serde_json::Value,
+    serde_json::{json, Value},
+    std::{fmt, str::FromStr, sync::Arc, time::Duration},
+    tokio::{
+        sync::Mutex,
+        time::{sleep_until, Instant},
+    },
 };
 
 const PUBLIC_API_URL: &str = "https://api.coin.z.com/public";
 const PRIVATE_API_URL: &str = "https://api.coin.z.com/private";
+const TRADE_ARCHIVE_URL: &str = "https://api.coin.z.com/data/trades";
+
+// Intervals of klines. The date of the request is a day (YYYYMMDD) for the intervals up to
+// 1hour, and a year (YYYY) for the others.
+pub const KLINE_INTERVALS: [&str; 12] = [
+    "1min", "5min", "10min", "15min", "30min", "1hour", "4hour", "8hour", "12hour", "1day",
+    "1week", "1month",
+];
+pub const DAILY_KLINE_INTERVALS: [&str; 6] = ["1min", "5min", "10min", "15min", "30min", "1hour"];
+
+// Side of an order or execution, written as BUY/SELL both by GMO and into the tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Buy => "BUY",
+            Side::Sell => "SELL",
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Side {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "BUY" => Ok(Side::Buy),
+            "SELL" => Ok(Side::Sell),
+            _ => Err(format!("unknown side: {}", s)),
+        }
+    }
+}
+
+// Whether an execution opens or closes a position. Spot executions are OPEN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SettleType {
+    Open,
+    Close,
+}
+
+impl SettleType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettleType::Open => "OPEN",
+            SettleType::Close => "CLOSE",
+        }
+    }
+}
+
+impl fmt::Display for SettleType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SettleType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "OPEN" => Ok(SettleType::Open),
+            "CLOSE" => Ok(SettleType::Close),
+            _ => Err(format!("unknown settle type: {}", s)),
+        }
+    }
+}
 
-#[derive(Debug)]
+// Symbol such as BTC for spot trading and BTC_JPY for leverage trading.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Symbol(String);
+
+impl Symbol {
+    pub fn new(s: &str) -> Self {
+        Symbol(s.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_leverage(&self) -> bool {
+        self.0.ends_with("_JPY")
+    }
+
+    // Crypto asset traded by the symbol, like BTC of BTC_JPY.
+    pub fn base(&self) -> &str {
+        self.0.strip_suffix("_JPY").unwrap_or(&self.0)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct GmoClient {
     api_key: String,
     api_secret: String,
     client: Client,
+    // Requests are sent at this interval at least. Clones of the client share the limit.
+    interval: Duration,
+    next_request: Arc<Mutex<Instant>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,6 +149,7 @@ pub struct StatusData {
     pub status: Status,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Deserialize)]
 pub enum Status {
     MAINTENANCE,
@@ -91,16 +209,16 @@ pub struct Execution {
     pub execution_id: i64,
     #[serde(rename(deserialize = "orderId"))]
     pub order_id: i64,
-    pub symbol: String,
-    pub side: String,
+    pub symbol: Symbol,
+    pub side: Side,
     #[serde(rename(deserialize = "settleType"))]
-    pub settle_type: String,
+    pub settle_type: SettleType,
     pub size: String,
     pub price: String,
     #[serde(rename(deserialize = "lossGain"))]
     pub loss_gain: String,
     pub fee: String,
-    pub timestamp: String,
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -122,8 +240,10 @@ pub struct Pagination {
 #[derive(Debug, Deserialize)]
 pub struct TickerResponse {
     pub status: u8,
+    #[serde(default)]
     pub data: Vec<TickerData>,
     pub responsetime: String,
+    pub messages: Option<ErrorMessages>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -138,18 +258,147 @@ pub struct TickerData {
     pub volume: String,
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct OrderbooksResponse {
+    pub status: u8,
+    pub responsetime: String,
+    pub data: Option<Orderbook>,
+    pub messages: Option<ErrorMessages>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct Orderbook {
+    pub asks: Vec<OrderbookLevel>,
+    pub bids: Vec<OrderbookLevel>,
+    pub symbol: String,
+    pub timestamp: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct OrderbookLevel {
+    pub price: String,
+    pub size: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct TradesResponse {
+    pub status: u8,
+    pub responsetime: String,
+    pub data: Option<TradesData>,
+    pub messages: Option<ErrorMessages>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct TradesData {
+    pub pagination: Option<Pagination>,
+    pub list: Option<Vec<Trade>>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct Trade {
+    pub price: String,
+    pub side: String,
+    pub size: String,
+    pub timestamp: String,
+    pub symbol: Option<String>,
+}
+
+// A row of the daily public trade archive. The timestamp is in JST like "2021-04-15 00:00:01.478".
+#[derive(Debug, Deserialize)]
+pub struct TradeArchiveRecord {
+    pub symbol: String,
+    pub side: String,
+    pub size: String,
+    pub price: String,
+    pub timestamp: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct KlinesResponse {
+    pub status: u8,
+    pub responsetime: String,
+    pub data: Option<Vec<Kline>>,
+    pub messages: Option<ErrorMessages>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Kline {
+    #[serde(rename(deserialize = "openTime"))]
+    pub open_time: String,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub volume: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct TransfersResponse {
+    pub status: u8,
+    pub responsetime: String,
+    pub data: Option<Vec<Transfer>>,
+    pub messages: Option<ErrorMessages>,
+}
+
+// A deposit or withdrawal of JPY or crypto. Fiat transfers carry no address or tx hash, and
+// deposits carry no fee.
+#[derive(Debug, Deserialize)]
+pub struct Transfer {
+    pub amount: String,
+    pub fee: Option<String>,
+    pub status: String,
+    pub symbol: String,
+    pub timestamp: String,
+    pub address: Option<String>,
+    #[serde(rename(deserialize = "txHash"))]
+    pub tx_hash: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct WsAuthResponse {
+    pub status: u8,
+    pub responsetime: String,
+    pub data: Option<String>,
+    pub messages: Option<ErrorMessages>,
+}
+
 impl GmoClient {
     pub fn new(api_key: String, api_secret: String) -> Self {
         GmoClient {
-            api_key: api_key,
-            api_secret: api_secret,
+            api_key,
+            api_secret,
             client: Client::builder().build().unwrap(),
+            interval: Duration::ZERO,
+            next_request: Arc::new(Mutex::new(Instant::now())),
         }
     }
 
+    // Limit the number of requests per second.
+    pub fn with_rate_limit(mut self, requests_per_second: u32) -> Self {
+        self.interval = Duration::from_secs(1) / requests_per_second.max(1);
+        self
+    }
+
+    // Wait until the rate limit allows the next request.
+    async fn wait(&self) {
+        let mut next = self.next_request.lock().await;
+        sleep_until(*next).await;
+        *next = Instant::now() + self.interval;
+    }
+
     #[allow(dead_code)]
     pub async fn status(&self) -> Result<StatusResponse, reqwest::Error> {
         let path = "/v1/status";
+        self.wait().await;
         let res = self
             .client
             .get(format!("{}{}", PUBLIC_API_URL, path))
@@ -171,6 +420,7 @@ impl GmoClient {
         if let Some(v) = symbol {
             query.push(("symbol", v));
         }
+        self.wait().await;
         let res = self
             .client
             .get(format!("{}{}", PUBLIC_API_URL, path))
@@ -183,10 +433,109 @@ impl GmoClient {
         Ok(res)
     }
 
+    // public api: /v1/orderbooks
+    pub async fn get_orderbooks(
+        &self,
+        symbol: String,
+    ) -> Result<OrderbooksResponse, reqwest::Error> {
+        let path = "/v1/orderbooks";
+        let query = vec![("symbol", symbol)];
+        self.wait().await;
+        let res = self
+            .client
+            .get(format!("{}{}", PUBLIC_API_URL, path))
+            .query(&query)
+            .send()
+            .await?
+            .json::<OrderbooksResponse>()
+            .await
+            .unwrap();
+        Ok(res)
+    }
+
+    // public api: /v1/trades
+    pub async fn get_trades(
+        &self,
+        symbol: String,
+        page: Option<i64>,
+        count: Option<i64>,
+    ) -> Result<TradesResponse, reqwest::Error> {
+        let path = "/v1/trades";
+        let mut query = vec![("symbol", symbol)];
+        if let Some(v) = page {
+            query.push(("page", v.to_string()));
+        }
+        if let Some(v) = count {
+            query.push(("count", v.to_string()));
+        }
+        self.wait().await;
+        let res = self
+            .client
+            .get(format!("{}{}", PUBLIC_API_URL, path))
+            .query(&query)
+            .send()
+            .await?
+            .json::<TradesResponse>()
+            .await
+            .unwrap();
+        Ok(res)
+    }
+
+    // Name of the daily public trade archive file of the symbol, like "20210415_BTC.csv.gz".
+    pub fn trade_archive_file_name(symbol: &str, date: NaiveDate) -> String {
+        format!("{}_{}.csv.gz", date.format("%Y%m%d"), symbol)
+    }
+
+    // public data: /data/trades/{symbol}/{YYYY}/{MM}/{YYYYMMDD}_{symbol}.csv.gz
+    // Returns the gzipped CSV, or None when the archive of the day has not been published.
+    pub async fn get_trade_archive(
+        &self,
+        symbol: &str,
+        date: NaiveDate,
+    ) -> Result<Option<Vec<u8>>, reqwest::Error> {
+        let url = format!(
+            "{}/{}/{}/{}",
+            TRADE_ARCHIVE_URL,
+            symbol,
+            date.format("%Y/%m"),
+            GmoClient::trade_archive_file_name(symbol, date)
+        );
+        self.wait().await;
+        let res = self.client.get(url).send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = res.error_for_status()?.bytes().await?;
+        Ok(Some(body.to_vec()))
+    }
+
+    // public api: /v1/klines
+    pub async fn get_klines(
+        &self,
+        symbol: String,
+        interval: String,
+        date: String,
+    ) -> Result<KlinesResponse, reqwest::Error> {
+        let path = "/v1/klines";
+        let query = vec![("symbol", symbol), ("interval", interval), ("date", date)];
+        self.wait().await;
+        let res = self
+            .client
+            .get(format!("{}{}", PUBLIC_API_URL, path))
+            .query(&query)
+            .send()
+            .await?
+            .json::<KlinesResponse>()
+            .await
+            .unwrap();
+        Ok(res)
+    }
+
     // private api: /v1/account/assets
     #[allow(dead_code)]
     pub async fn get_assets(&self) -> Result<AssetesResponse, reqwest::Error> {
         let path = "/v1/account/assets";
+        self.wait().await;
         let res = self
             .client
             .get(format!("{}{}", PRIVATE_API_URL, path))
@@ -214,6 +563,7 @@ impl GmoClient {
             query.push(("executionId", v));
         }
         let path = "/v1/executions";
+        self.wait().await;
         let res = self
             .client
             .get(format!("{}{}", PRIVATE_API_URL, path))
@@ -244,6 +594,7 @@ impl GmoClient {
         }
 
         let path = "/v1/latestExecutions";
+        self.wait().await;
         let res = self
             .client
             .get(format!("{}{}", PRIVATE_API_URL, path))
@@ -256,6 +607,141 @@ impl GmoClient {
         Ok(res)
     }
 
+    // private api: /v1/account/fiatDeposit/history
+    pub async fn get_fiat_deposit_history(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<TransfersResponse, reqwest::Error> {
+        self.get_transfer_history("/v1/account/fiatDeposit/history", None, from, to)
+            .await
+    }
+
+    // private api: /v1/account/fiatWithdrawal/history
+    pub async fn get_fiat_withdrawal_history(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<TransfersResponse, reqwest::Error> {
+        self.get_transfer_history("/v1/account/fiatWithdrawal/history", None, from, to)
+            .await
+    }
+
+    // private api: /v1/account/deposit/history
+    pub async fn get_deposit_history(
+        &self,
+        symbol: String,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<TransfersResponse, reqwest::Error> {
+        self.get_transfer_history("/v1/account/deposit/history", Some(symbol), from, to)
+            .await
+    }
+
+    // private api: /v1/account/withdrawal/history
+    pub async fn get_withdrawal_history(
+        &self,
+        symbol: String,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<TransfersResponse, reqwest::Error> {
+        self.get_transfer_history("/v1/account/withdrawal/history", Some(symbol), from, to)
+            .await
+    }
+
+    async fn get_transfer_history(
+        &self,
+        path: &str,
+        symbol: Option<String>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<TransfersResponse, reqwest::Error> {
+        let mut query = vec![];
+        if let Some(v) = symbol {
+            query.push(("symbol", v));
+        }
+        query.push((
+            "fromTimestamp",
+            from.to_rfc3339_opts(SecondsFormat::Millis, true),
+        ));
+        query.push((
+            "toTimestamp",
+            to.to_rfc3339_opts(SecondsFormat::Millis, true),
+        ));
+        self.wait().await;
+        let res = self
+            .client
+            .get(format!("{}{}", PRIVATE_API_URL, path))
+            .query(&query)
+            .headers(self.create_auth_headers("GET", path, Some("")))
+            .send()
+            .await?
+            .json::<TransfersResponse>()
+            .await
+            .unwrap();
+        Ok(res)
+    }
+
+    // private api: POST /v1/ws-auth
+    pub async fn create_ws_auth_token(&self) -> Result<WsAuthResponse, reqwest::Error> {
+        let path = "/v1/ws-auth";
+        let body = "{}";
+        self.wait().await;
+        let res = self
+            .client
+            .post(format!("{}{}", PRIVATE_API_URL, path))
+            .headers(self.create_auth_headers("POST", path, Some(body)))
+            .body(body)
+            .send()
+            .await?
+            .json::<WsAuthResponse>()
+            .await
+            .unwrap();
+        Ok(res)
+    }
+
+    // private api: PUT /v1/ws-auth
+    pub async fn extend_ws_auth_token(
+        &self,
+        token: &str,
+    ) -> Result<WsAuthResponse, reqwest::Error> {
+        let path = "/v1/ws-auth";
+        let body = json!({ "token": token }).to_string();
+        self.wait().await;
+        let res = self
+            .client
+            .put(format!("{}{}", PRIVATE_API_URL, path))
+            .headers(self.create_auth_headers("PUT", path, Some(&body)))
+            .body(body)
+            .send()
+            .await?
+            .json::<WsAuthResponse>()
+            .await
+            .unwrap();
+        Ok(res)
+    }
+
+    // private api: DELETE /v1/ws-auth
+    pub async fn delete_ws_auth_token(
+        &self,
+        token: &str,
+    ) -> Result<WsAuthResponse, reqwest::Error> {
+        let path = "/v1/ws-auth";
+        let body = json!({ "token": token }).to_string();
+        self.wait().await;
+        let res = self
+            .client
+            .delete(format!("{}{}", PRIVATE_API_URL, path))
+            .headers(self.create_auth_headers("DELETE", path, Some(&body)))
+            .body(body)
+            .send()
+            .await?
+            .json::<WsAuthResponse>()
+            .await
+            .unwrap();
+        Ok(res)
+    }
+
     fn create_auth_headers(&self, method: &str, path: &str, data: Option<&str>) -> HeaderMap {
         let timestamp = Utc::now().timestamp_millis().to_string();
         let text = format!("{}{}{}{}", timestamp, method, path, data.unwrap());
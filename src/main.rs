@@ -1,10 +1,21 @@
 mod bq;
+mod config;
+mod daemon;
 mod gmo;
 mod models;
+mod position;
 mod subcommands;
+mod tax;
+mod ws;
 
-use clap::{Arg, Command};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use clap::{Arg, ArgAction, Command};
+use config::Config;
 use dotenv::dotenv;
+use position::{CostMethod, OpeningPosition};
+use std::{process, time::Duration};
+use tax::ExportFormat;
 
 const COMMAND_STATUS: &str = "status";
 
@@ -13,40 +24,364 @@ async fn main() {
     dotenv().ok();
 
     let app = Command::new("gmo")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .global(true)
+                .help("Path of the configuration file [default: gmo.toml]"),
+        )
+        .arg(
+            Arg::new("tz")
+                .long("tz")
+                .global(true)
+                .value_parser(parse_tz)
+                .default_value("Asia/Tokyo")
+                .help("Time zone to display times in, such as UTC"),
+        )
         .subcommand(Command::new("my_executions"))
         .subcommand(Command::new("assets"))
-        .subcommand(Command::new("get_executions_by_order").arg(Arg::new("path").required(true)))
-        .subcommand(Command::new("average_price"))
+        .subcommand(
+            Command::new("get_executions_by_order")
+                .arg(
+                    Arg::new("path")
+                        .required_unless_present("order-id")
+                        .help("File of order IDs in CSV, one per line or JSON array; - for stdin"),
+                )
+                .arg(
+                    Arg::new("column")
+                        .long("column")
+                        .help("Header name or index from 0 of the column of order IDs [default: 0]"),
+                )
+                .arg(
+                    Arg::new("no-header")
+                        .long("no-header")
+                        .action(ArgAction::SetTrue)
+                        .help("The CSV has no header row"),
+                )
+                .arg(
+                    Arg::new("order-id")
+                        .long("order-id")
+                        .action(ArgAction::Append)
+                        .help("Order ID to be fetched, which can be repeated"),
+                ),
+        )
+        .subcommand(
+            Command::new("import-trade-report").arg(
+                Arg::new("path")
+                    .required(true)
+                    .num_args(1..)
+                    .help("Trade report CSV files downloaded from GMO"),
+            ),
+        )
+        .subcommand(
+            Command::new("average_price").arg(
+                Arg::new("method")
+                    .long("method")
+                    .value_parser(clap::value_parser!(CostMethod))
+                    .default_value("moving")
+                    .help("Cost basis method: moving, total or fifo"),
+            ),
+        )
+        .subcommand(
+            Command::new("positions")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("rebuild")
+                        .arg(
+                            Arg::new("method")
+                                .long("method")
+                                .value_parser(clap::value_parser!(CostMethod))
+                                .default_value("moving")
+                                .help("Cost basis method: moving, total or fifo"),
+                        )
+                        .arg(
+                            Arg::new("from")
+                                .long("from")
+                                .value_parser(parse_date)
+                                .help("First date (YYYY-MM-DD in JST) to recalculate from; earlier rows are kept"),
+                        )
+                        .arg(
+                            Arg::new("opening")
+                                .long("opening")
+                                .requires("from")
+                                .action(ArgAction::Append)
+                                .value_parser(clap::value_parser!(OpeningPosition))
+                                .help("Position at the beginning of --from as SYMBOL=SIZE@PRICE, which can be repeated; other symbols continue from their last position before it"),
+                        )
+                        .arg(
+                            Arg::new("suffix")
+                                .long("suffix")
+                                .help("Write into positions_<SUFFIX> and realized_pnl_<SUFFIX> instead of replacing the rows"),
+                        ),
+                ),
+        )
         .subcommand(Command::new("ticker"))
+        .subcommand(Command::new("transfers"))
+        .subcommand(
+            Command::new("klines")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_parser(parse_date)
+                        .help("First date (YYYY-MM-DD) of klines to backfill, of which a day starts at 6:00 JST"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_parser(parse_date)
+                        .help("Last date (YYYY-MM-DD) of klines to backfill [default: today]"),
+                ),
+        )
+        .subcommand(
+            Command::new("orderbook")
+                .arg(
+                    Arg::new("stream")
+                        .long("stream")
+                        .action(ArgAction::SetTrue)
+                        .help("Keep receiving order books from the WebSocket API"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("10")
+                        .help("Seconds between snapshots of a symbol in streaming"),
+                ),
+        )
+        .subcommand(Command::new("trades"))
+        .subcommand(
+            Command::new("import-trade-archive")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .required(true)
+                        .value_parser(parse_date)
+                        .help("First date (YYYY-MM-DD in JST) to import"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_parser(parse_date)
+                        .help("Last date (YYYY-MM-DD in JST) to import [default: yesterday]"),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .help("Directory of archive files; missing ones are downloaded into it"),
+                ),
+        )
+        .subcommand(
+            Command::new("aggregate")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_parser(parse_date)
+                        .help("First date (YYYY-MM-DD in JST) to recompute"),
+                )
+                .arg(
+                    Arg::new("lookback")
+                        .long("lookback")
+                        .value_parser(parse_lookback)
+                        .help("Period before the last candle to recompute, like 10min [default: 1hour]"),
+                ),
+        )
+        .subcommand(
+            Command::new("tax-report")
+                .arg(
+                    Arg::new("year")
+                        .long("year")
+                        .required(true)
+                        .value_parser(clap::value_parser!(i32))
+                        .help("Year (JST) of the report"),
+                )
+                .arg(
+                    Arg::new("method")
+                        .long("method")
+                        .value_parser(clap::value_parser!(CostMethod))
+                        .default_value("moving")
+                        .help("Cost basis method: moving, total or fifo"),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .default_value(".")
+                        .help("Directory where the CSV and Markdown files are written"),
+                ),
+        )
+        .subcommand(
+            Command::new("export-tax-csv")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(clap::value_parser!(ExportFormat))
+                        .default_value("cryptact")
+                        .help("Output format: cryptact or generic ([export] in the config)"),
+                )
+                .arg(
+                    Arg::new("year")
+                        .long("year")
+                        .value_parser(clap::value_parser!(i32))
+                        .help("Year (JST) of the executions [default: all]"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("Path of the CSV file [default: stdout]"),
+                ),
+        )
+        .subcommand(Command::new("stream"))
+        .subcommand(Command::new("daemon"))
+        .subcommand(Command::new("config").subcommand(Command::new("check")))
         .subcommand(Command::new(COMMAND_STATUS));
 
-    match app.get_matches().subcommand() {
+    let matches = app.get_matches();
+    let mut config = match Config::load(matches.get_one::<String>("config").map(|s| s.as_str())) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Config error: {}", e);
+            process::exit(1);
+        }
+    };
+    config.tz = *matches.get_one::<Tz>("tz").unwrap();
+
+    match matches.subcommand() {
         // Get latest executions and save them to the BigQuery.
         Some(("my_executions", _)) => {
-            subcommands::get_my_executions().await;
+            subcommands::get_my_executions(&config).await;
         }
         // Get current assets from GMO-Coin and save them to the BigQuery.
         Some(("assets", _)) => {
-            subcommands::get_assets().await;
+            subcommands::get_assets(&config).await;
         }
-        // Get execution information of specifeid order IDs in csv file and save them to the
-        // BigQuery.
+        // Get execution information of specifeid order IDs in a file or arguments and save them
+        // to the BigQuery.
         Some(("get_executions_by_order", args)) => {
-            let path = args.get_one::<String>("path").unwrap();
-            subcommands::get_executions_by_order(path.to_string()).await;
+            let path = args.get_one::<String>("path").map(|s| s.as_str());
+            let column = args.get_one::<String>("column").map(|s| s.as_str());
+            let inline: Vec<String> = args
+                .get_many::<String>("order-id")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default();
+            subcommands::get_executions_by_order(
+                &config,
+                path,
+                column,
+                args.get_flag("no-header"),
+                inline,
+            )
+            .await;
+        }
+        // Import executions from trade report CSV files and save them to the BigQuery.
+        Some(("import-trade-report", args)) => {
+            let paths: Vec<String> = args.get_many::<String>("path").unwrap().cloned().collect();
+            subcommands::import_trade_report(&config, &paths).await;
         }
-        // Calculate average buy price of own position.
-        Some(("average_price", _)) => {
-            subcommands::get_avg_price().await;
+        // Calculate the average price and cost basis of own position by the method.
+        Some(("average_price", args)) => {
+            let method = *args.get_one::<CostMethod>("method").unwrap();
+            subcommands::get_avg_price(&config, method).await;
+        }
+        // Recalculate the positions and realized profits from all the executions.
+        Some(("positions", args)) => {
+            if let Some(("rebuild", args)) = args.subcommand() {
+                let method = *args.get_one::<CostMethod>("method").unwrap();
+                let from = args.get_one::<NaiveDate>("from").copied();
+                let openings: Vec<OpeningPosition> = args
+                    .get_many::<OpeningPosition>("opening")
+                    .map(|v| v.cloned().collect())
+                    .unwrap_or_default();
+                let suffix = args.get_one::<String>("suffix").map(|s| s.as_str());
+                subcommands::rebuild_positions(&config, method, from, &openings, suffix).await;
+            }
         }
         Some(("ticker", _)) => {
-            subcommands::get_ticker().await;
+            subcommands::get_ticker(&config).await;
+        }
+        // Get deposit and withdrawal history and save new ones to the BigQuery.
+        Some(("transfers", _)) => {
+            subcommands::get_transfers(&config).await;
+        }
+        // Get klines of the configured symbols and intervals and save them to the BigQuery.
+        Some(("klines", args)) => {
+            let from = args.get_one::<NaiveDate>("from").copied();
+            let to = args.get_one::<NaiveDate>("to").copied();
+            subcommands::get_klines(&config, from, to).await;
+        }
+        // Get order book snapshots and save them and their depth metrics to the BigQuery.
+        Some(("orderbook", args)) => {
+            if args.get_flag("stream") {
+                let interval = *args.get_one::<u64>("interval").unwrap();
+                subcommands::stream_orderbooks(&config, Duration::from_secs(interval)).await;
+            } else {
+                subcommands::get_orderbooks(&config).await;
+            }
+        }
+        // Get new public trades and save them to the BigQuery.
+        Some(("trades", _)) => {
+            subcommands::get_trades(&config).await;
+        }
+        // Import the daily public trade archives of GMO and save them to the BigQuery.
+        Some(("import-trade-archive", args)) => {
+            let from = *args.get_one::<NaiveDate>("from").unwrap();
+            let to = args.get_one::<NaiveDate>("to").copied();
+            let dir = args.get_one::<String>("dir").map(|s| s.as_str());
+            subcommands::import_trade_archive(&config, from, to, dir).await;
+        }
+        // Build candles from the stored public trades and save them to the BigQuery.
+        Some(("aggregate", args)) => {
+            let from = args.get_one::<NaiveDate>("from").copied();
+            let lookback = args.get_one::<i64>("lookback").copied();
+            subcommands::aggregate(&config, from, lookback).await;
         }
+        // Write the yearly summary of the holdings for the tax return.
+        Some(("tax-report", args)) => {
+            let year = *args.get_one::<i32>("year").unwrap();
+            let method = *args.get_one::<CostMethod>("method").unwrap();
+            let out = args.get_one::<String>("out").unwrap();
+            subcommands::tax_report(&config, year, method, out).await;
+        }
+        // Export executions as a CSV file for tax services.
+        Some(("export-tax-csv", args)) => {
+            let format = *args.get_one::<ExportFormat>("format").unwrap();
+            let year = args.get_one::<i32>("year").copied();
+            let output = args.get_one::<String>("output").map(|s| s.as_str());
+            subcommands::export_tax_csv(&config, format, year, output).await;
+        }
+        // Receive executions continuously and save them to the BigQuery as they arrive.
+        Some(("stream", _)) => {
+            subcommands::stream(&config).await;
+        }
+        // Run the jobs above on their schedules in one process.
+        Some(("daemon", _)) => {
+            daemon::run(config).await;
+        }
+        // Validate the configuration and report missing secrets.
+        Some(("config", args)) => match args.subcommand() {
+            Some(("check", _)) => {
+                subcommands::check_config(&config);
+            }
+            _ => {
+                println!("None");
+            }
+        },
         Some((COMMAND_STATUS, _)) => {
-            subcommands::status().await;
+            subcommands::status(&config).await;
         }
         _ => {
             println!("None");
         }
     }
 }
+
+fn parse_date(v: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(v, "%Y-%m-%d").map_err(|e| e.to_string())
+}
+
+fn parse_tz(v: &str) -> Result<Tz, String> {
+    v.parse::<Tz>()
+}
+
+fn parse_lookback(v: &str) -> Result<i64, String> {
+    config::parse_interval(v).ok_or_else(|| format!("invalid period: {}", v))
+}
@@ -1,38 +1,152 @@
-use serde::Serialize;
+use crate::gmo::{SettleType, Side, Symbol};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+
+// Write time into TIMESTAMP columns in UTC with milliseconds.
+fn serialize_timestamp<S: Serializer>(t: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&t.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
 
 #[derive(Serialize, Debug)]
 pub struct MyExecutions {
     pub execution_id: i64,
     pub order_id: i64,
-    pub symbol: String,
-    pub side: String,
-    pub settle_type: String,
-    pub size: f64,
-    pub price: f64,
-    pub loss_gain: f64,
-    pub fee: f64,
-    pub timestamp: String,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub settle_type: SettleType,
+    pub size: Decimal,
+    pub price: Decimal,
+    pub loss_gain: Decimal,
+    pub fee: Decimal,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct Assets {
-    pub timestamp: String,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
     pub symbol: String,
-    pub amount: f64,
-    pub available: f64,
+    pub amount: Decimal,
+    pub available: Decimal,
+    pub conversion_rate: Decimal,
+    pub jpy_value: Decimal,
 }
 
 #[derive(Serialize, Debug)]
 pub struct Positions {
-    pub timestamp: String,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
     pub execution_id: i64,
-    pub average_price: f64,
-    pub size: f64,
+    pub symbol: Symbol,
+    pub method: String,
+    pub average_price: Decimal,
+    pub size: Decimal,
+    pub cost: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RealizedPnl {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub execution_id: i64,
+    pub symbol: Symbol,
+    pub method: String,
+    pub size: Decimal,
+    pub price: Decimal,
+    pub proceeds: Decimal,
+    pub cost_basis: Decimal,
+    pub fee: Decimal,
+    pub realized_pnl: Decimal,
+    pub loss_gain: Decimal,
+    pub discrepancy: bool,
+}
+
+// Position from which a rebuild started each symbol, which the following calculations start from.
+#[derive(Serialize, Debug)]
+pub struct PositionOpenings {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    pub symbol: Symbol,
+    pub size: Decimal,
+    pub price: Decimal,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub rebuilt_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct Ticker {
-    pub timestamp: String,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub symbol: String,
+    pub ask: Decimal,
+    pub bid: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub last: Decimal,
+    pub volume: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Transfers {
+    pub transaction_id: String,
+    pub transfer_type: String,
     pub symbol: String,
-    pub last: f64,
+    pub amount: Decimal,
+    pub fee: Decimal,
+    pub status: String,
+    pub address: Option<String>,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Klines {
+    pub symbol: String,
+    pub interval: String,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub open_time: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Orderbooks {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub symbol: String,
+    pub side: String,
+    pub level: usize,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OrderbookMetrics {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub symbol: String,
+    pub best_bid: Decimal,
+    pub best_ask: Decimal,
+    pub spread: Decimal,
+    pub mid_price: Decimal,
+    pub bid_depth_0_5: Decimal,
+    pub ask_depth_0_5: Decimal,
+    pub bid_depth_1: Decimal,
+    pub ask_depth_1: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MarketTrades {
+    pub symbol: String,
+    pub side: String,
+    pub price: Decimal,
+    pub size: Decimal,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
 }
@@ -0,0 +1,576 @@
+use {
+    crate::{
+        gmo::{SettleType, Side, Symbol},
+        models::MyExecutions,
+    },
+    chrono::{DateTime, Datelike, FixedOffset, Utc},
+    rust_decimal::Decimal,
+    std::{collections::VecDeque, fmt, str::FromStr},
+};
+
+// Method to calculate the cost basis of the position.
+//   moving: 移動平均法, the average is updated by every purchase.
+//   total:  総平均法, the average of the opening position and all purchases of the year (JST).
+//   fifo:   先入先出法, sales consume the oldest purchases first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CostMethod {
+    Moving,
+    Total,
+    Fifo,
+}
+
+impl CostMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CostMethod::Moving => "moving",
+            CostMethod::Total => "total",
+            CostMethod::Fifo => "fifo",
+        }
+    }
+}
+
+impl fmt::Display for CostMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CostMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "moving" => Ok(CostMethod::Moving),
+            "total" => Ok(CostMethod::Total),
+            "fifo" => Ok(CostMethod::Fifo),
+            _ => Err(format!("unknown method: {} (moving, total or fifo)", s)),
+        }
+    }
+}
+
+// An execution to be applied to the position.
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub timestamp: DateTime<Utc>,
+    pub side: Side,
+    pub settle_type: SettleType,
+    pub size: Decimal,
+    pub price: Decimal,
+    pub fee: Decimal,
+}
+
+impl From<&MyExecutions> for Fill {
+    fn from(e: &MyExecutions) -> Self {
+        Fill {
+            timestamp: e.timestamp,
+            side: e.side,
+            settle_type: e.settle_type,
+            size: e.size,
+            price: e.price,
+            fee: e.fee,
+        }
+    }
+}
+
+// Position of a symbol given at the beginning of a recalculation, as SYMBOL=SIZE@PRICE. The size is
+// negative for a short position.
+#[derive(Debug, Clone)]
+pub struct OpeningPosition {
+    pub symbol: Symbol,
+    pub size: Decimal,
+    pub price: Decimal,
+}
+
+impl FromStr for OpeningPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid opening position: {} (SYMBOL=SIZE@PRICE)", s);
+        let (symbol, rest) = s.split_once('=').ok_or_else(invalid)?;
+        let (size, price) = rest.split_once('@').ok_or_else(invalid)?;
+        Ok(OpeningPosition {
+            symbol: Symbol::new(symbol.trim()),
+            size: size.trim().parse().map_err(|_| invalid())?,
+            price: price.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// Position after an execution. The size is negative for a short position. The cost is the amount
+// paid for a long position including the fees, and the amount received for a short position net
+// of the fees. closed_cost is the cost of the amount closed by the execution, and realized is the
+// profit of it before closed_fee, the part of the fee for closing. oversold is the amount of a spot
+// sale exceeding the holding, which is ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionStep {
+    pub size: Decimal,
+    pub average_price: Decimal,
+    pub cost: Decimal,
+    pub closed_size: Decimal,
+    pub closed_cost: Decimal,
+    pub closed_fee: Decimal,
+    pub realized: Decimal,
+    pub oversold: Decimal,
+}
+
+// Calculator of the position of one symbol. Executions must be applied in time order.
+//
+// An execution against the position closes it first, and the rest opens a position on the other
+// side, unless the execution is to settle (CLOSE), which never opens one. Spot sales are OPEN, so
+// they reduce the holding the same way, but never open a short position. Long and short positions
+// held at once on leverage trading are netted.
+#[derive(Debug)]
+pub struct PositionCalculator {
+    method: CostMethod,
+    spot: bool,
+    size: Decimal,
+    cost: Decimal,
+    // opened amounts which are not closed yet, as (size, cost), for fifo
+    lots: VecDeque<(Decimal, Decimal)>,
+    // year (JST) and the opening long position and purchases of the year, for total
+    year: Option<i32>,
+    year_size: Decimal,
+    year_cost: Decimal,
+}
+
+impl PositionCalculator {
+    pub fn new(method: CostMethod) -> Self {
+        PositionCalculator {
+            method,
+            spot: false,
+            size: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            lots: VecDeque::new(),
+            year: None,
+            year_size: Decimal::ZERO,
+            year_cost: Decimal::ZERO,
+        }
+    }
+
+    // Calculator of the symbol, which cannot go short on spot trading.
+    pub fn for_symbol(symbol: &Symbol, method: CostMethod) -> Self {
+        PositionCalculator {
+            spot: !symbol.is_leverage(),
+            ..Self::new(method)
+        }
+    }
+
+    pub fn apply(&mut self, fill: &Fill) -> PositionStep {
+        self.roll_year(fill.timestamp);
+
+        // amount to close the opposite position, and amount to open
+        let against = match fill.side {
+            Side::Buy => self.size < Decimal::ZERO,
+            Side::Sell => self.size > Decimal::ZERO,
+        };
+        let closed_size = if against {
+            fill.size.min(self.size.abs())
+        } else {
+            Decimal::ZERO
+        };
+        let mut opened_size = match fill.settle_type {
+            SettleType::Open => fill.size - closed_size,
+            SettleType::Close => Decimal::ZERO,
+        };
+        let oversold = if self.spot && fill.side == Side::Sell {
+            opened_size
+        } else {
+            Decimal::ZERO
+        };
+        opened_size -= oversold;
+
+        // the fee is divided between closing and opening by the amount
+        let opened_fee = if fill.size.is_zero() {
+            Decimal::ZERO
+        } else {
+            fill.fee * opened_size / fill.size
+        };
+        let closed_fee = fill.fee - opened_fee;
+
+        let closed_cost = self.close(closed_size);
+        let realized = match fill.side {
+            Side::Sell => fill.price * closed_size - closed_cost,
+            Side::Buy => closed_cost - fill.price * closed_size,
+        };
+        if opened_size > Decimal::ZERO {
+            let cost = match fill.side {
+                Side::Buy => fill.price * opened_size + opened_fee,
+                Side::Sell => fill.price * opened_size - opened_fee,
+            };
+            self.open(fill.side, opened_size, cost);
+        }
+
+        PositionStep {
+            size: self.size,
+            average_price: self.average_price(),
+            cost: self.cost,
+            closed_size,
+            closed_cost,
+            closed_fee,
+            realized,
+            oversold,
+        }
+    }
+
+    // Start from the position held at the time instead of nothing. The cost is the size at the
+    // price.
+    pub fn set_opening(&mut self, timestamp: DateTime<Utc>, size: Decimal, price: Decimal) {
+        self.roll_year(timestamp);
+        let side = if size < Decimal::ZERO {
+            Side::Sell
+        } else {
+            Side::Buy
+        };
+        self.open(side, size.abs(), size.abs() * price);
+    }
+
+    // Add the amount received by a deposit, of which the cost is unknown and regarded as zero.
+    pub fn deposit(&mut self, timestamp: DateTime<Utc>, size: Decimal) {
+        self.roll_year(timestamp);
+        self.open(Side::Buy, size, Decimal::ZERO);
+    }
+
+    // Remove the amount sent by a withdrawal from the holding and return its cost basis.
+    pub fn withdraw(&mut self, timestamp: DateTime<Utc>, size: Decimal) -> Decimal {
+        self.roll_year(timestamp);
+        self.close(size.min(self.size.max(Decimal::ZERO)))
+    }
+
+    // Current size and cost of the long position. By total, the cost is valued at the average of
+    // the year so far.
+    pub fn holding(&self) -> (Decimal, Decimal) {
+        if self.size <= Decimal::ZERO {
+            return (Decimal::ZERO, Decimal::ZERO);
+        }
+        match self.method {
+            CostMethod::Total => (self.size, self.size * self.average_price()),
+            _ => (self.size, self.cost),
+        }
+    }
+
+    pub fn average_price(&self) -> Decimal {
+        match self.method {
+            CostMethod::Total if self.size > Decimal::ZERO && self.year_size > Decimal::ZERO => {
+                self.year_cost / self.year_size
+            }
+            _ if !self.size.is_zero() => self.cost / self.size.abs(),
+            _ => Decimal::ZERO,
+        }
+    }
+
+    // Average of the opening long position and the purchases of the year, for total, which stays
+    // after the holding is gone.
+    pub fn year_average_price(&self) -> Decimal {
+        if self.year_size > Decimal::ZERO {
+            self.year_cost / self.year_size
+        } else {
+            Decimal::ZERO
+        }
+    }
+
+    // Add the amount to the position on the side, which must be flat or on the same side.
+    fn open(&mut self, side: Side, size: Decimal, cost: Decimal) {
+        match side {
+            Side::Buy => self.size += size,
+            Side::Sell => self.size -= size,
+        }
+        self.cost += cost;
+        match self.method {
+            CostMethod::Moving => {}
+            CostMethod::Total => {
+                if side == Side::Buy {
+                    self.year_size += size;
+                    self.year_cost += cost;
+                }
+            }
+            CostMethod::Fifo => self.lots.push_back((size, cost)),
+        }
+    }
+
+    // Close the amount of the position, which must not exceed it, and return its cost basis.
+    fn close(&mut self, size: Decimal) -> Decimal {
+        if size.is_zero() {
+            return Decimal::ZERO;
+        }
+        let closed_cost = match self.method {
+            CostMethod::Total if self.size > Decimal::ZERO => size * self.average_price(),
+            _ if size == self.size.abs() => self.cost,
+            CostMethod::Moving | CostMethod::Total => size * self.cost / self.size.abs(),
+            CostMethod::Fifo => {
+                let mut rest = size;
+                let mut cost = Decimal::ZERO;
+                while rest > Decimal::ZERO {
+                    let Some(lot) = self.lots.front_mut() else {
+                        break;
+                    };
+                    if rest >= lot.0 {
+                        cost += lot.1;
+                        rest -= lot.0;
+                        self.lots.pop_front();
+                    } else {
+                        let part = lot.1 * rest / lot.0;
+                        cost += part;
+                        lot.0 -= rest;
+                        lot.1 -= part;
+                        rest = Decimal::ZERO;
+                    }
+                }
+                cost
+            }
+        };
+        if self.size > Decimal::ZERO {
+            self.size -= size;
+        } else {
+            self.size += size;
+        }
+        self.cost -= closed_cost;
+
+        // start over from nothing once flat
+        if self.size.is_zero() {
+            self.cost = Decimal::ZERO;
+            self.lots.clear();
+        }
+        closed_cost
+    }
+
+    // At the beginning of a year, carry the long position over at the average of the last year.
+    // Until the year ends, the average is that of the purchases so far.
+    fn roll_year(&mut self, timestamp: DateTime<Utc>) {
+        if self.method != CostMethod::Total {
+            return;
+        }
+        let jst = FixedOffset::east_opt(9 * 60 * 60).unwrap();
+        let year = timestamp.with_timezone(&jst).year();
+        if self.year == Some(year) {
+            return;
+        }
+        let long = self.size > Decimal::ZERO;
+        if self.year.is_some() && long {
+            self.cost = self.size * self.average_price();
+        }
+        self.year = Some(year);
+        self.year_size = if long { self.size } else { Decimal::ZERO };
+        self.year_cost = if long { self.cost } else { Decimal::ZERO };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, chrono::TimeZone};
+
+    fn d(v: &str) -> Decimal {
+        v.parse().unwrap()
+    }
+
+    fn fill(side: Side, settle_type: SettleType, size: &str, price: &str, fee: &str) -> Fill {
+        Fill {
+            timestamp: Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap(),
+            side,
+            settle_type,
+            size: d(size),
+            price: d(price),
+            fee: d(fee),
+        }
+    }
+
+    fn buy(size: &str, price: &str) -> Fill {
+        fill(Side::Buy, SettleType::Open, size, price, "0")
+    }
+
+    fn sell(size: &str, price: &str) -> Fill {
+        fill(Side::Sell, SettleType::Open, size, price, "0")
+    }
+
+    #[test]
+    fn moving_average_of_long_position() {
+        let mut calc = PositionCalculator::new(CostMethod::Moving);
+        calc.apply(&fill(Side::Buy, SettleType::Open, "1", "100", "2"));
+        let step = calc.apply(&buy("1", "200"));
+        assert_eq!(step.size, d("2"));
+        assert_eq!(step.average_price, d("151"));
+
+        let step = calc.apply(&sell("0.5", "300"));
+        assert_eq!(step.size, d("1.5"));
+        assert_eq!(step.closed_size, d("0.5"));
+        assert_eq!(step.closed_cost, d("75.5"));
+        assert_eq!(step.realized, d("74.5"));
+        assert_eq!(step.average_price, d("151"));
+    }
+
+    #[test]
+    fn flat_position_starts_over() {
+        let mut calc = PositionCalculator::new(CostMethod::Moving);
+        calc.apply(&buy("1", "100"));
+        let step = calc.apply(&sell("1", "120"));
+        assert_eq!(step.size, Decimal::ZERO);
+        assert_eq!(step.cost, Decimal::ZERO);
+        assert_eq!(step.average_price, Decimal::ZERO);
+        assert_eq!(step.realized, d("20"));
+
+        // the next purchase is not averaged with the previous price
+        let step = calc.apply(&buy("0.5", "200"));
+        assert_eq!(step.size, d("0.5"));
+        assert_eq!(step.average_price, d("200"));
+        assert_eq!(step.cost, d("100"));
+    }
+
+    #[test]
+    fn short_position_is_opened_and_closed() {
+        let mut calc = PositionCalculator::new(CostMethod::Moving);
+        let step = calc.apply(&fill(Side::Sell, SettleType::Open, "2", "100", "0"));
+        assert_eq!(step.size, d("-2"));
+        assert_eq!(step.average_price, d("100"));
+        assert_eq!(step.cost, d("200"));
+        assert_eq!(step.closed_size, Decimal::ZERO);
+
+        let step = calc.apply(&fill(Side::Sell, SettleType::Open, "1", "130", "0"));
+        assert_eq!(step.size, d("-3"));
+        assert_eq!(step.average_price, d("110"));
+
+        let step = calc.apply(&fill(Side::Buy, SettleType::Close, "1", "90", "0"));
+        assert_eq!(step.size, d("-2"));
+        assert_eq!(step.closed_cost, d("110"));
+        assert_eq!(step.realized, d("20"));
+        assert_eq!(step.average_price, d("110"));
+
+        // a settlement never opens a position beyond the one to close
+        let step = calc.apply(&fill(Side::Buy, SettleType::Close, "3", "120", "0"));
+        assert_eq!(step.size, Decimal::ZERO);
+        assert_eq!(step.closed_size, d("2"));
+        assert_eq!(step.realized, d("-20"));
+        assert_eq!(step.cost, Decimal::ZERO);
+    }
+
+    #[test]
+    fn settlement_without_position_is_ignored() {
+        let mut calc = PositionCalculator::new(CostMethod::Moving);
+        let step = calc.apply(&fill(Side::Sell, SettleType::Close, "1", "100", "1"));
+        assert_eq!(step.size, Decimal::ZERO);
+        assert_eq!(step.closed_size, Decimal::ZERO);
+        assert_eq!(step.realized, Decimal::ZERO);
+        assert_eq!(step.closed_fee, d("1"));
+    }
+
+    #[test]
+    fn position_crosses_zero() {
+        let mut calc = PositionCalculator::new(CostMethod::Moving);
+        calc.apply(&buy("1", "100"));
+        let step = calc.apply(&fill(Side::Sell, SettleType::Open, "3", "110", "3"));
+        assert_eq!(step.closed_size, d("1"));
+        assert_eq!(step.closed_cost, d("100"));
+        assert_eq!(step.realized, d("10"));
+        assert_eq!(step.closed_fee, d("1"));
+        // the rest opens a short at the price, net of its part of the fee
+        assert_eq!(step.size, d("-2"));
+        assert_eq!(step.cost, d("218"));
+        assert_eq!(step.average_price, d("109"));
+
+        let step = calc.apply(&buy("3", "100"));
+        assert_eq!(step.closed_size, d("2"));
+        assert_eq!(step.realized, d("18"));
+        assert_eq!(step.size, d("1"));
+        assert_eq!(step.average_price, d("100"));
+    }
+
+    #[test]
+    fn fifo_crosses_zero() {
+        let mut calc = PositionCalculator::new(CostMethod::Fifo);
+        calc.apply(&buy("1", "100"));
+        calc.apply(&buy("1", "200"));
+        let step = calc.apply(&sell("1.5", "150"));
+        assert_eq!(step.closed_cost, d("200"));
+        assert_eq!(step.average_price, d("200"));
+
+        let step = calc.apply(&sell("1.5", "150"));
+        assert_eq!(step.closed_size, d("0.5"));
+        assert_eq!(step.closed_cost, d("100"));
+        assert_eq!(step.realized, d("-25"));
+        assert_eq!(step.size, d("-1"));
+        assert_eq!(step.average_price, d("150"));
+
+        let step = calc.apply(&buy("1", "140"));
+        assert_eq!(step.closed_cost, d("150"));
+        assert_eq!(step.realized, d("10"));
+        assert_eq!(step.size, Decimal::ZERO);
+    }
+
+    #[test]
+    fn total_average_with_short() {
+        let mut calc = PositionCalculator::new(CostMethod::Total);
+        calc.apply(&buy("1", "100"));
+        calc.apply(&buy("1", "200"));
+        let step = calc.apply(&sell("3", "120"));
+        assert_eq!(step.closed_size, d("2"));
+        assert_eq!(step.closed_cost, d("300"));
+        assert_eq!(step.realized, d("-60"));
+        assert_eq!(step.size, d("-1"));
+        assert_eq!(step.average_price, d("120"));
+        assert_eq!(calc.holding(), (Decimal::ZERO, Decimal::ZERO));
+    }
+
+    #[test]
+    fn total_average_is_carried_over_to_next_year() {
+        let mut calc = PositionCalculator::new(CostMethod::Total);
+        calc.apply(&buy("1", "100"));
+        calc.apply(&buy("1", "300"));
+        calc.apply(&sell("1", "250"));
+
+        let mut next = buy("1", "400");
+        next.timestamp = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let step = calc.apply(&next);
+        assert_eq!(step.size, d("2"));
+        assert_eq!(step.average_price, d("300"));
+    }
+
+    #[test]
+    fn opening_position_is_taken_over() {
+        let opening: OpeningPosition = "BTC_JPY=-0.5@4000000".parse().unwrap();
+        assert_eq!(opening.symbol.as_str(), "BTC_JPY");
+        assert!("BTC=0.5".parse::<OpeningPosition>().is_err());
+
+        let mut calc = PositionCalculator::new(CostMethod::Moving);
+        let t = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        calc.set_opening(t, opening.size, opening.price);
+        let step = calc.apply(&fill(Side::Buy, SettleType::Close, "0.5", "3900000", "0"));
+        assert_eq!(step.closed_cost, d("2000000"));
+        assert_eq!(step.realized, d("50000"));
+        assert_eq!(step.size, Decimal::ZERO);
+    }
+
+    #[test]
+    fn withdrawal_does_not_open_short() {
+        let mut calc = PositionCalculator::new(CostMethod::Moving);
+        calc.apply(&buy("1", "100"));
+        let cost = calc.withdraw(Utc.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap(), d("2"));
+        assert_eq!(cost, d("100"));
+        assert_eq!(calc.holding(), (Decimal::ZERO, Decimal::ZERO));
+
+        calc.deposit(Utc.with_ymd_and_hms(2023, 8, 1, 0, 0, 0).unwrap(), d("1"));
+        assert_eq!(calc.holding(), (d("1"), Decimal::ZERO));
+    }
+
+    #[test]
+    fn spot_sale_does_not_open_short() {
+        let mut calc = PositionCalculator::for_symbol(&Symbol::new("BTC"), CostMethod::Moving);
+        calc.apply(&buy("1", "100"));
+        let step = calc.apply(&fill(Side::Sell, SettleType::Open, "1.5", "120", "3"));
+        assert_eq!(step.size, Decimal::ZERO);
+        assert_eq!(step.closed_size, d("1"));
+        assert_eq!(step.realized, d("20"));
+        assert_eq!(step.oversold, d("0.5"));
+        // the whole fee is for the amount closed
+        assert_eq!(step.closed_fee, d("3"));
+
+        // a sale without holding is ignored
+        let step = calc.apply(&sell("1", "120"));
+        assert_eq!(step.size, Decimal::ZERO);
+        assert_eq!(step.closed_size, Decimal::ZERO);
+        assert_eq!(step.oversold, d("1"));
+
+        // leverage trading goes short
+        let mut calc = PositionCalculator::for_symbol(&Symbol::new("BTC_JPY"), CostMethod::Moving);
+        let step = calc.apply(&sell("1", "120"));
+        assert_eq!(step.size, d("-1"));
+        assert_eq!(step.oversold, Decimal::ZERO);
+    }
+}
@@ -1,73 +1,191 @@
 use crate::{
-    bq::{create_bq_client, insert_bq},
-    gmo::{Execution, GmoClient},
-    models::{Assets, MyExecutions, Positions, Ticker},
+    bq::{
+        create_bq_client, execute_dml, insert_bq, insert_bq_rows, insert_bq_rows_with, query_all,
+    },
+    config::{parse_interval, Config},
+    gmo::{
+        Execution, ExecutionData, ExecutionsResponse, GmoClient, Kline, KlinesResponse,
+        LatestExecutionsResponse, Orderbook, OrderbookLevel, OrderbooksResponse, SettleType, Side,
+        Symbol, TickerData, Trade, TradeArchiveRecord, TradesData, TradesResponse, Transfer,
+        TransfersResponse, DAILY_KLINE_INTERVALS,
+    },
+    models::{
+        Assets, Klines, MarketTrades, MyExecutions, OrderbookMetrics, Orderbooks, PositionOpenings,
+        Positions, RealizedPnl, Ticker, Transfers,
+    },
+    position::{CostMethod, Fill, OpeningPosition, PositionCalculator},
+    tax::{self, ExportFormat, LeverageSummary, TaxEvent},
+    ws::{
+        PrivateChannel, PrivateEvent, PrivateWsClient, PublicChannel, PublicEvent, PublicWsClient,
+    },
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use encoding_rs::SHIFT_JIS;
+use flate2::read::GzDecoder;
+use futures_util::StreamExt;
 use gcp_bigquery_client::model::{
-    query_request::QueryRequest, table_data_insert_all_request::TableDataInsertAllRequest,
+    query_request::QueryRequest, query_response::ResultSet,
+    table_data_insert_all_request::TableDataInsertAllRequest,
 };
-use std::env;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    fs,
+    io::Write,
+    ops::RangeInclusive,
+    path::Path,
+    process,
+    str::FromStr,
+    time::Duration,
+};
+
+// GMO's dates such as the date of klines are in JST.
+const JST_OFFSET_SECS: i32 = 9 * 60 * 60;
+
+// A day of GMO's klines starts at this hour in JST.
+const KLINE_DAY_START_HOURS: i32 = 6;
+
+// Symbol of the assets row which holds the total value of all assets.
+const TOTAL_ASSETS_SYMBOL: &str = "TOTAL";
+
+// Transfers are collected from this time when the transfers table is empty and no start date is
+// configured.
+const TRANSFERS_START: &str = "2017-01-01T00:00:00Z";
+
+// Status of the deposits and withdrawals which have been completed.
+const TRANSFER_EXECUTED: &str = "EXECUTED";
+
+// Maximum period that one deposit/withdrawal history request covers.
+const TRANSFERS_WINDOW_DAYS: i64 = 30;
+
+// Public trades are read in pages of this size, up to the maximum pages in one run.
+const TRADES_PAGE_SIZE: i64 = 100;
+const TRADES_MAX_PAGES: i64 = 100;
 
-const DATASET_ID: &str = "gmo";
+// Difference in JPY between the realized profit and GMO's loss_gain which is flagged.
+const PNL_TOLERANCE: Decimal = Decimal::ONE;
+
+// Orders fetched at the same time, and the number of orders saved at once, in
+// get_executions_by_order.
+const ORDERS_CONCURRENCY: usize = 4;
+const ORDERS_FLUSH_SIZE: usize = 100;
+
+// Candles from this long before the last stored one are recomputed to take in late trades.
+const AGGREGATE_LOOKBACK_SECS: i64 = 60 * 60;
+
+// Interval of reconciliation against the latest executions, which saves executions missed while
+// the stream was disconnected.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// The latest executions are read in pages of this size, up to the maximum pages. They cover the
+// last day, so the IDs of executions older than that are forgotten by the stream.
+const RECONCILE_PAGE_SIZE: i64 = 100;
+const RECONCILE_MAX_PAGES: i64 = 100;
+const RECONCILE_RETENTION_HOURS: i64 = 25;
+
+// create GMO API client
+fn create_gmo_client(config: &Config) -> GmoClient {
+    GmoClient::new(
+        config.api_key().to_string(),
+        config.api_secret().to_string(),
+    )
+    .with_rate_limit(config.gmo.requests_per_second)
+}
+
+pub fn check_config(config: &Config) {
+    let problems = config.check();
+    if problems.is_empty() {
+        for job in config.jobs() {
+            println!(
+                "Job {}: symbols={:?}, schedule='{}', timeout={:?}",
+                job.name, job.symbols, job.schedule, job.timeout
+            );
+        }
+        println!("Config OK.");
+    } else {
+        for p in problems {
+            println!("Config error: {}", p);
+        }
+        process::exit(1);
+    }
+}
 
-pub async fn status() {
+pub async fn status(config: &Config) {
     // create GMO API client
-    let gmo = GmoClient::new(
-        env::var("API_KEY").unwrap(),
-        env::var("API_SECRET").unwrap(),
-    );
+    let gmo = create_gmo_client(config);
     let status = gmo.status().await.unwrap();
     println!("Status: {:?}", status);
 }
 
-pub async fn get_assets() {
+pub async fn get_assets(config: &Config) {
     // create GMO API client
-    let gmo = GmoClient::new(
-        env::var("API_KEY").unwrap(),
-        env::var("API_SECRET").unwrap(),
-    );
+    let gmo = create_gmo_client(config);
     // get balance from GMO
     let assets = gmo.get_assets().await.unwrap();
 
+    // symbols to be saved, or every symbol when empty
+    let symbols = config.job("assets").symbols;
+
     let mut ins_req = TableDataInsertAllRequest::new();
-    match assets.data {
-        Some(data) => {
-            let ts = Utc::now().format("%Y-%m-%d %H:%M:%S");
-            for d in data {
-                match &*d.symbol {
-                    "JPY" | "BTC" => {
-                        let a = Assets {
-                            timestamp: ts.to_string(),
-                            amount: d.amount.parse::<f64>().unwrap(),
-                            available: d.available.parse::<f64>().unwrap(),
-                            symbol: d.symbol,
-                        };
-                        println!("Assets: {} {}", a.amount, a.symbol);
-                        ins_req.add_row(None, a).unwrap()
-                    }
-                    _ => {}
-                }
+    if let Some(data) = assets.data {
+        let ts = Utc::now();
+        let mut total = Decimal::ZERO;
+        let mut total_available = Decimal::ZERO;
+        for d in data {
+            let amount = d.amount.parse::<Decimal>().unwrap();
+            if amount.is_zero() {
+                continue;
+            }
+            let available = d.available.parse::<Decimal>().unwrap();
+            let conversion_rate = d.conversion_rate.parse::<Decimal>().unwrap();
+            let jpy_value = amount * conversion_rate;
+            total += jpy_value;
+            total_available += available * conversion_rate;
+
+            if symbols.is_empty() || symbols.contains(&d.symbol) {
+                let a = Assets {
+                    timestamp: ts,
+                    symbol: d.symbol,
+                    amount,
+                    available,
+                    conversion_rate,
+                    jpy_value,
+                };
+                println!("Assets: {} {} ({:.0} JPY)", a.amount, a.symbol, a.jpy_value);
+                ins_req.add_row(None, a).unwrap()
             }
         }
-        _ => {}
+
+        // value of the whole account in JPY
+        let a = Assets {
+            timestamp: ts,
+            symbol: String::from(TOTAL_ASSETS_SYMBOL),
+            amount: total,
+            available: total_available,
+            conversion_rate: Decimal::ONE,
+            jpy_value: total,
+        };
+        println!("Assets: {:.0} JPY in total", a.jpy_value);
+        ins_req.add_row(None, a).unwrap()
     }
 
-    insert_bq(ins_req, DATASET_ID, "assets").await;
+    insert_bq(config, ins_req, "assets").await;
 }
 
 // Get latest executions within 24 hours and save them into BigQuery.
-pub async fn get_my_executions() {
+pub async fn get_my_executions(config: &Config) {
     // select latest execution_id from BigQuery
-    let project_id = &env::var("BQ_PROJECT_ID").unwrap();
+    let project_id = config.project_id();
     let table_id = "my_executions";
     let query = format!(
-        "select max(execution_id) as execution_id from {}.{}.{}",
-        project_id, DATASET_ID, table_id
+        "select max(execution_id) as execution_id from {}",
+        config.table(table_id)
     );
 
     // Search latest execution from BigQuery
-    let bq_client = create_bq_client().await;
+    let bq_client = create_bq_client(config).await;
     let mut rs = bq_client
         .job()
         .query(project_id, QueryRequest::new(query))
@@ -89,226 +207,2288 @@ pub async fn get_my_executions() {
     let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
 
     // create GMO API client
-    let gmo = GmoClient::new(
-        env::var("API_KEY").unwrap(),
-        env::var("API_SECRET").unwrap(),
-    );
+    let gmo = create_gmo_client(config);
+
+    for symbol in &config.job("my_executions").symbols {
+        // get latest executions by GMO api (within 24 hours)
+        let executions = &gmo.get_latest_executions(symbol.clone(), None, None).await;
 
-    // get latest executions by GMO api (within 24 hours)
-    let executions = &gmo
-        .get_latest_executions(String::from("BTC"), None, None)
-        .await;
+        match executions {
+            Ok(exec) => {
+                if let Some(data) = &exec.data {
+                    if let Some(list) = &data.list {
+                        for e in list {
+                            if e.execution_id > latest_execution_id {
+                                println!(
+                                    "Found new excution: id={}, timestamp={}, side={}, price={}, size={}",
+                                    e.execution_id,
+                                    config.local_time(&e.timestamp),
+                                    e.side,
+                                    e.price,
+                                    e.size
+                                );
+                                ins_req.add_row(None, convert_my_executions(e)).unwrap();
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("{:?}", e)
+            }
+        }
+    }
 
-    match executions {
-        Ok(exec) => {
-            if let Some(data) = &exec.data {
-                if let Some(list) = &data.list {
-                    // initial values
-                    let mut pos = 0.35641193;
-                    let mut pos_price = 1044768.46741743;
-                    let mut avg_buy_price = 2931351.0;
+    insert_bq(config, ins_req, table_id).await;
+}
 
-                    for e in list {
-                        if e.execution_id > latest_execution_id {
-                            let size = e.size.parse::<f64>().unwrap();
-                            let price = e.price.parse::<f64>().unwrap();
-                            if e.side == "BUY" {
-                                pos += size;
-                                pos_price += size * price;
-                                avg_buy_price = pos_price / pos
-                            } else {
-                                pos -= e.size.parse::<f64>().unwrap();
-                                pos_price -= avg_buy_price * size;
-                            };
+// Get executions of the given order IDs and save them into BigQuery. Order IDs are read from the
+// file ("-" for stdin) and the inline ones. When read from a file, processed orders are recorded
+// in a checkpoint file next to it, so that an interrupted run can be resumed.
+pub async fn get_executions_by_order(
+    config: &Config,
+    path: Option<&str>,
+    column: Option<&str>,
+    no_header: bool,
+    inline: Vec<String>,
+) {
+    // order IDs without duplicates
+    let mut order_ids: Vec<String> = vec![];
+    let mut listed: HashSet<String> = HashSet::new();
+    let mut input = inline;
+    if let Some(p) = path {
+        let text = if p == "-" {
+            std::io::read_to_string(std::io::stdin()).unwrap()
+        } else {
+            fs::read_to_string(p).unwrap()
+        };
+        match parse_order_ids(&text, column, no_header) {
+            Ok(ids) => input.extend(ids),
+            Err(e) => {
+                println!("{}: {}", p, e);
+                process::exit(1);
+            }
+        }
+    }
+    for order_id in input {
+        if listed.insert(order_id.clone()) {
+            order_ids.push(order_id);
+        }
+    }
 
-                            println!(
-                            "Found new excution: id={}, timestamp={}, side={}, price={}, size={}, avg_buy_price={}",
-                            e.execution_id, e.timestamp, e.side, e.price, e.size, avg_buy_price);
+    // skip the orders of which executions are already saved
+    let project_id = config.project_id();
+    let table_id = "my_executions";
+    let query = format!("select distinct order_id from {}", config.table(table_id));
+    let bq_client = create_bq_client(config).await;
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+    let mut done: HashSet<String> = HashSet::new();
+    for mut rs in pages {
+        while rs.next_row() {
+            done.insert(rs.get_i64_by_name("order_id").unwrap().unwrap().to_string());
+        }
+    }
 
-                            ins_req.add_row(None, convert_my_executions(&e)).unwrap();
-                        }
+    // and the orders processed by the previous runs, including ones without executions
+    let checkpoint = path
+        .filter(|p| *p != "-")
+        .map(|p| format!("{}.checkpoint", p));
+    if let Some(text) = checkpoint.as_ref().and_then(|c| fs::read_to_string(c).ok()) {
+        done.extend(text.lines().map(|l| l.trim().to_string()));
+    }
+
+    let total = order_ids.len();
+    order_ids.retain(|id| !done.contains(id));
+    println!(
+        "{} orders, {} already processed, {} to be fetched",
+        total,
+        total - order_ids.len(),
+        order_ids.len()
+    );
+
+    // create GMO API client
+    let gmo = create_gmo_client(config);
+
+    // fetch concurrently, while the client keeps the rate limit
+    let mut results = futures_util::stream::iter(order_ids)
+        .map(|order_id| {
+            let gmo = &gmo;
+            async move {
+                let res = gmo.get_executions(Some(order_id.clone()), None).await;
+                (order_id, res)
+            }
+        })
+        .buffer_unordered(ORDERS_CONCURRENCY);
+
+    let mut fetched: Vec<String> = vec![];
+    let mut rows: Vec<MyExecutions> = vec![];
+    let (mut processed, mut saved, mut failed) = (0, 0, 0);
+    loop {
+        let next = results.next().await;
+        match &next {
+            Some((
+                order_id,
+                Ok(ExecutionsResponse {
+                    data: Some(data), ..
+                }),
+            )) => {
+                for e in data.list.iter().flatten() {
+                    rows.push(convert_my_executions(e));
+                }
+                fetched.push(order_id.clone());
+            }
+            Some((order_id, Ok(ExecutionsResponse { messages, .. }))) => {
+                println!("  order {}: {:?}", order_id, messages);
+                failed += 1;
+            }
+            Some((order_id, Err(e))) => {
+                println!("  order {}: {:?}", order_id, e);
+                failed += 1;
+            }
+            None => {}
+        }
+
+        // save periodically so that a crash loses only the last orders
+        if fetched.len() >= ORDERS_FLUSH_SIZE || (next.is_none() && !fetched.is_empty()) {
+            if insert_bq_rows(config, &rows, table_id).await {
+                if let Some(c) = &checkpoint {
+                    let mut file = fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(c)
+                        .unwrap();
+                    for id in &fetched {
+                        writeln!(file, "{}", id).unwrap();
                     }
                 }
+                processed += fetched.len();
+                saved += rows.len();
+            } else {
+                failed += fetched.len();
             }
+            fetched.clear();
+            rows.clear();
+            println!(
+                "Progress: {} processed, {} executions saved, {} failed",
+                processed, saved, failed
+            );
         }
-        Err(e) => {
-            println!("{:?}", e)
+        if next.is_none() {
+            break;
+        }
+    }
+    println!(
+        "Finished: {} processed, {} executions saved, {} failed. Failed orders are retried by the next run.",
+        processed, saved, failed
+    );
+}
+
+// Parse order IDs from a JSON array, or CSV including a plain list of one ID per line. The column
+// is a header name or an index from 0, and the first column by default. Unless the column is a
+// header name, the header is detected by whether the column of the first line is an ID.
+fn parse_order_ids(
+    text: &str,
+    column: Option<&str>,
+    no_header: bool,
+) -> Result<Vec<String>, String> {
+    let text = text.trim_start_matches('\u{feff}').trim();
+    if text.starts_with('[') {
+        let values: Vec<Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        return values
+            .into_iter()
+            .map(|v| match v {
+                Value::String(s) => Ok(s.trim().to_string()),
+                Value::Number(n) => Ok(n.to_string()),
+                v => Err(format!("not an order ID: {}", v)),
+            })
+            .collect();
+    }
+
+    // a column given by name needs the header, while with the one by index the header is
+    // detected by the field of the column
+    let position = match column {
+        None => Some(0),
+        Some(c) => c.parse::<usize>().ok(),
+    };
+    let has_headers = !no_header && !position.is_some_and(|i| first_field_is_id(text, i));
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(has_headers)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let index = match (position, column) {
+        (Some(i), _) => i,
+        (None, Some(c)) if has_headers => reader
+            .headers()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .position(|h| h.trim() == c)
+            .ok_or(format!("no column {}", c))?,
+        (None, c) => return Err(format!("column {} needs the header", c.unwrap_or_default())),
+    };
+
+    let mut ids = vec![];
+    for r in reader.records() {
+        let r = r.map_err(|e| e.to_string())?;
+        let id = r.get(index).unwrap_or("").trim();
+        if !id.is_empty() {
+            ids.push(id.to_string());
         }
     }
+    Ok(ids)
+}
 
-    insert_bq(ins_req, DATASET_ID, table_id).await;
+// Whether the field at the index of the first line of the CSV is an order ID.
+fn first_field_is_id(text: &str, index: usize) -> bool {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes())
+        .records()
+        .next()
+        .and_then(|r| r.ok())
+        .is_some_and(|r| {
+            r.get(index)
+                .is_some_and(|v| v.trim().parse::<i64>().is_ok())
+        })
 }
 
-pub async fn get_executions_by_order(order_id_csv_file_path: String) {
-    // create GMO API client
-    let gmo = GmoClient::new(
-        env::var("API_KEY").unwrap(),
-        env::var("API_SECRET").unwrap(),
+// Import executions from trade report (取引報告書) CSV files downloaded from GMO's web console and
+// save the ones not saved yet into BigQuery.
+pub async fn import_trade_report(config: &Config, paths: &[String]) {
+    // select the executions already saved
+    let project_id = config.project_id();
+    let table_id = "my_executions";
+    let query = format!("select execution_id from {}", config.table(table_id));
+
+    let bq_client = create_bq_client(config).await;
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+    let mut saved: HashSet<i64> = HashSet::new();
+    for mut rs in pages {
+        while rs.next_row() {
+            saved.insert(rs.get_i64_by_name("execution_id").unwrap().unwrap());
+        }
+    }
+
+    let mut executions: Vec<MyExecutions> = vec![];
+    for path in paths {
+        match read_trade_report(path) {
+            Ok(list) => {
+                let total = list.len();
+                for e in list {
+                    if saved.insert(e.execution_id) {
+                        executions.push(e);
+                    }
+                }
+                println!("{}: {} executions", path, total);
+            }
+            Err(e) => println!("{}: {}", path, e),
+        }
+    }
+    println!("{} new executions", executions.len());
+    insert_bq_rows(config, &executions, table_id).await;
+}
+
+// Parse a trade report CSV. It is encoded in Shift_JIS, and rows other than executions such as
+// deposits have no execution ID and are ignored.
+fn read_trade_report(path: &str) -> Result<Vec<MyExecutions>, String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    // UTF-8 files with BOM, such as those saved by Excel, are also accepted
+    let (text, _, malformed) = SHIFT_JIS.decode(&bytes);
+    if malformed {
+        return Err(String::from("not encoded in Shift_JIS"));
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim() == name)
+            .ok_or(format!("no column {}", name))
+    };
+    let timestamp_col = column("日時")?;
+    let execution_id_col = column("約定ID")?;
+    let order_id_col = column("注文ID")?;
+    let symbol_col = column("銘柄名")?;
+    let side_col = column("売買区分")?;
+    let settle_type_col = column("取引区分")?;
+    let size_col = column("約定数量")?;
+    let price_col = column("約定レート")?;
+    let fee_col = column("注文手数料")?;
+    let loss_gain_col = column("決済損益")?;
+
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let mut executions = vec![];
+    for (i, r) in reader.records().enumerate() {
+        let r = r.map_err(|e| e.to_string())?;
+        let field = |col: usize| r.get(col).unwrap_or("").trim();
+        let number = |col: usize| -> Result<Decimal, String> {
+            let v = field(col).replace(',', "");
+            if v.is_empty() {
+                return Ok(Decimal::ZERO);
+            }
+            v.parse::<Decimal>()
+                .map_err(|e| format!("line {}: {}: {}", i + 2, v, e))
+        };
+        let execution_id = match field(execution_id_col).parse::<i64>() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let t = NaiveDateTime::parse_from_str(field(timestamp_col), "%Y/%m/%d %H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(field(timestamp_col), "%Y/%m/%d %H:%M"))
+            .map_err(|e| format!("line {}: {}: {}", i + 2, field(timestamp_col), e))?;
+        let timestamp = jst.from_local_datetime(&t).unwrap().with_timezone(&Utc);
+
+        let side = match field(side_col) {
+            "買" => Side::Buy,
+            "売" => Side::Sell,
+            v => return Err(format!("line {}: unknown 売買区分 {}", i + 2, v)),
+        };
+        // spot trades are regarded as opening like the API does
+        let settle_type = match field(settle_type_col) {
+            "決済" => SettleType::Close,
+            _ => SettleType::Open,
+        };
+
+        executions.push(MyExecutions {
+            execution_id,
+            order_id: field(order_id_col).parse::<i64>().unwrap_or(0),
+            // leverage symbols are written like BTC/JPY
+            symbol: Symbol::new(&field(symbol_col).replace('/', "_")),
+            side,
+            settle_type,
+            size: number(size_col)?,
+            price: number(price_col)?,
+            loss_gain: number(loss_gain_col)?,
+            fee: number(fee_col)?,
+            timestamp,
+        });
+    }
+    Ok(executions)
+}
+
+// Receive executions from the private WebSocket API and save each of them into BigQuery as soon
+// as it arrives.
+pub async fn stream(config: &Config) {
+    // select executions within 24 hours from BigQuery, which reconciliation may find again
+    let project_id = config.project_id();
+    let table_id = "my_executions";
+    let since = Utc::now() - chrono::Duration::hours(RECONCILE_RETENTION_HOURS);
+    let query = format!(
+        "select execution_id, timestamp from {} where timestamp >= '{}'",
+        config.table(table_id),
+        since.format("%Y-%m-%d %H:%M:%S")
     );
 
-    let mut order_ids: Vec<String> = vec![];
+    let bq_client = create_bq_client(config).await;
+    let mut rs = bq_client
+        .job()
+        .query(project_id, QueryRequest::new(query))
+        .await
+        .unwrap();
+    let mut execution_ids: HashMap<i64, DateTime<Utc>> = HashMap::new();
+    while rs.next_row() {
+        let id = rs.get_i64_by_name("execution_id").unwrap().unwrap();
+        let ts = rs.get_string_by_name("timestamp").unwrap().unwrap();
+        execution_ids.insert(id, parse_bq_timestamp(&ts));
+    }
+    println!(
+        "Stored executions within the last day: {}",
+        execution_ids.len()
+    );
 
-    let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
-    let mut reader = csv::Reader::from_path(order_id_csv_file_path).unwrap();
-    for rec in reader.records() {
-        if let Ok(r) = rec {
-            let order_id = r.get(0).unwrap().to_string();
-            println!("order_id: {:?}", order_id);
-
-            if order_ids.contains(&order_id) {
-                println!("  already processed: {:?}", order_id);
-                continue;
+    // create GMO API client
+    let gmo = create_gmo_client(config);
+
+    let mut ws = PrivateWsClient::connect(gmo.clone());
+    ws.subscribe(PrivateChannel::Execution);
+
+    // the first tick completes immediately, so gaps before starting are filled at first
+    let mut reconciliation = tokio::time::interval(RECONCILE_INTERVAL);
+    loop {
+        tokio::select! {
+            event = ws.next() => match event {
+                Some(PrivateEvent::Execution(event)) => {
+                    let e = Execution::from(&event);
+                    if execution_ids.insert(e.execution_id, e.timestamp).is_some() {
+                        continue;
+                    }
+                    println!(
+                        "Received execution: id={}, timestamp={}, side={}, price={}, size={}",
+                        e.execution_id, config.local_time(&e.timestamp), e.side, e.price, e.size);
+
+                    let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+                    ins_req.add_row(None, convert_my_executions(&e)).unwrap();
+                    if !insert_bq(config, ins_req, table_id).await {
+                        // leave it to reconciliation
+                        execution_ids.remove(&e.execution_id);
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            },
+            _ = reconciliation.tick() => {
+                reconcile_executions(config, &gmo, &mut execution_ids, table_id).await;
             }
-            order_ids.push(order_id.clone());
+        }
+    }
+}
 
-            //  get execution by order_id from GMO
-            let executions = gmo.get_executions(Some(order_id), None).await.unwrap();
+// Save executions within the last day which are not in the given execution IDs, and forget the
+// IDs older than that.
+async fn reconcile_executions(
+    config: &Config,
+    gmo: &GmoClient,
+    execution_ids: &mut HashMap<i64, DateTime<Utc>>,
+    table_id: &str,
+) {
+    let since = Utc::now() - chrono::Duration::hours(RECONCILE_RETENTION_HOURS);
+    execution_ids.retain(|_, t| *t >= since);
+
+    let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+    let mut new_ids: Vec<(i64, DateTime<Utc>)> = vec![];
 
-            if let Some(data) = executions.data {
-                if let Some(list) = data.list {
+    for symbol in &config.job("my_executions").symbols {
+        // executions are listed from the newest, so read pages until a stored one appears
+        for page in 1..=RECONCILE_MAX_PAGES {
+            match gmo
+                .get_latest_executions(symbol.clone(), Some(page), Some(RECONCILE_PAGE_SIZE))
+                .await
+            {
+                Ok(LatestExecutionsResponse {
+                    data: Some(ExecutionData { list: Some(list) }),
+                    ..
+                }) => {
+                    let reached = list.len() < RECONCILE_PAGE_SIZE as usize
+                        || list
+                            .last()
+                            .is_some_and(|e| execution_ids.contains_key(&e.execution_id));
                     for e in list {
-                        println!("  execution: {:?}", e.execution_id);
-                        ins_req.add_row(None, convert_my_executions(&e)).unwrap();
+                        if !execution_ids.contains_key(&e.execution_id) {
+                            println!(
+                                "Found missing execution: id={}, timestamp={}, side={}, price={}, size={}",
+                                e.execution_id,
+                                config.local_time(&e.timestamp),
+                                e.side,
+                                e.price,
+                                e.size
+                            );
+                            new_ids.push((e.execution_id, e.timestamp));
+                            ins_req.add_row(None, convert_my_executions(&e)).unwrap();
+                        }
+                    }
+                    if reached {
+                        break;
                     }
                 }
+                Ok(_) => break,
+                Err(e) => {
+                    println!("{:?}", e);
+                    break;
+                }
             }
-            std::thread::sleep_ms(1000);
         }
     }
 
-    insert_bq(ins_req, DATASET_ID, "my_executions").await;
+    if insert_bq(config, ins_req, table_id).await {
+        execution_ids.extend(new_ids);
+    }
 }
 
 fn convert_my_executions(e: &Execution) -> MyExecutions {
-    // convert date time format
-    let d = DateTime::parse_from_rfc3339(&e.timestamp).unwrap();
-    let timestamp = d.format("%Y-%m-%d %H:%M:%S").to_string();
-
     // return the execution as a MyExecutions
     MyExecutions {
         execution_id: e.execution_id,
         order_id: e.order_id,
         symbol: e.symbol.clone(),
-        side: e.side.clone(),
-        settle_type: e.settle_type.clone(),
-        size: e.size.parse::<f64>().unwrap(),
-        price: e.price.parse::<f64>().unwrap(),
-        loss_gain: e.loss_gain.parse::<f64>().unwrap(),
-        fee: e.fee.parse::<f64>().unwrap(),
-        timestamp: timestamp,
+        side: e.side,
+        settle_type: e.settle_type,
+        size: e.size.parse::<Decimal>().unwrap(),
+        price: e.price.parse::<Decimal>().unwrap(),
+        loss_gain: e.loss_gain.parse::<Decimal>().unwrap(),
+        fee: e.fee.parse::<Decimal>().unwrap(),
+        timestamp: e.timestamp,
+    }
+}
+
+// Read a row of my_executions returned from BigQuery.
+fn read_my_executions(rs: &ResultSet) -> MyExecutions {
+    let ts = rs.get_string_by_name("timestamp").unwrap().unwrap();
+    let side = rs.get_string_by_name("side").unwrap().unwrap();
+    let settle_type = rs.get_string_by_name("settle_type").unwrap().unwrap();
+    MyExecutions {
+        execution_id: rs.get_i64_by_name("execution_id").unwrap().unwrap(),
+        order_id: rs.get_i64_by_name("order_id").unwrap().unwrap(),
+        symbol: Symbol::new(&rs.get_string_by_name("symbol").unwrap().unwrap()),
+        side: side.parse::<Side>().unwrap(),
+        settle_type: settle_type.parse::<SettleType>().unwrap(),
+        size: get_decimal(rs, "size"),
+        price: get_decimal(rs, "price"),
+        loss_gain: get_decimal(rs, "loss_gain"),
+        fee: get_decimal(rs, "fee"),
+        timestamp: parse_bq_timestamp(&ts),
     }
 }
 
-pub async fn get_avg_price() {
-    // get the average price saved last time
-    let project_id = &env::var("BQ_PROJECT_ID").unwrap();
+// Calculate the position of each symbol after every execution by the method, and the realized
+// profit of every execution closing the position, and save the ones not saved yet into BigQuery.
+// By the total method, the average of the year changes with every purchase in it, so only the
+// ones of the years closed (JST) are saved. Symbols rebuilt by rebuild_positions start from the
+// position it started from.
+pub async fn get_avg_price(config: &Config, method: CostMethod) {
+    // get the executions of which the position has been saved with the method
+    let project_id = config.project_id();
     let query = format!(
-        "
-        select
-          execution_id,
-          size,
-          average_price
-        from
-        (
-          select *
-          from {}.{}.positions
-          order by
-            execution_id desc
-        )
-        limit 1",
-        project_id, DATASET_ID
+        "select execution_id from {} where method = '{}'",
+        config.table("positions"),
+        method
     );
 
-    let bq_client = create_bq_client().await;
-    let mut rs = bq_client
-        .job()
-        .query(project_id, QueryRequest::new(query))
-        .await
-        .unwrap();
+    let bq_client = create_bq_client(config).await;
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+    let mut saved: HashSet<i64> = HashSet::new();
+    for mut rs in pages {
+        while rs.next_row() {
+            saved.insert(rs.get_i64_by_name("execution_id").unwrap().unwrap());
+        }
+    }
+    println!("{} executions in positions by {}", saved.len(), method);
 
-    let mut latest_pos_exec_id: i64 = 0;
-    let mut cum_size: f64 = 0.0;
-    let mut avg_price: f64 = 0.0;
+    // and the executions of which the realized profit has been saved
+    let query = format!(
+        "select execution_id from {} where method = '{}'",
+        config.table("realized_pnl"),
+        method
+    );
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+    let mut realized: HashSet<i64> = HashSet::new();
+    for mut rs in pages {
+        while rs.next_row() {
+            realized.insert(rs.get_i64_by_name("execution_id").unwrap().unwrap());
+        }
+    }
 
-    if rs.next_row() {
-        latest_pos_exec_id = rs.get_i64_by_name("execution_id").unwrap().unwrap();
-        cum_size = rs.get_f64_by_name("size").unwrap().unwrap();
-        avg_price = rs.get_f64_by_name("average_price").unwrap().unwrap();
+    // the position is recalculated from the first execution, or from the opening position of the
+    // last rebuild, because the cost basis depends on all the executions before
+    let query = format!(
+        "select symbol, timestamp, size, price from {} where method = '{}'
+        qualify row_number() over (partition by symbol order by rebuilt_at desc) = 1",
+        config.table("position_openings"),
+        method
+    );
+    let mut calculators: HashMap<Symbol, PositionCalculator> = HashMap::new();
+    let mut starts: HashMap<Symbol, DateTime<Utc>> = HashMap::new();
+    match query_all(&bq_client, project_id, query).await {
+        Ok(pages) => {
+            for mut rs in pages {
+                while rs.next_row() {
+                    let symbol = Symbol::new(&rs.get_string_by_name("symbol").unwrap().unwrap());
+                    let ts = rs.get_string_by_name("timestamp").unwrap().unwrap();
+                    let t = parse_bq_timestamp(&ts);
+                    let size = get_decimal(&rs, "size");
+                    let price = get_decimal(&rs, "price");
+                    println!(
+                        "{} starts from {} at {} at {}",
+                        symbol,
+                        size,
+                        price,
+                        config.local_time(&t)
+                    );
+                    let mut calc = PositionCalculator::for_symbol(&symbol, method);
+                    if !size.is_zero() {
+                        calc.set_opening(t, size, price);
+                    }
+                    calculators.insert(symbol.clone(), calc);
+                    starts.insert(symbol, t);
+                }
+            }
+        }
+        Err(e) => println!("Opening positions are not taken into account: {:?}", e),
     }
-    println!("latest execution_id in positions: {}", latest_pos_exec_id);
 
-    // get executions records which the average price has not yet been calculated.
     let query = format!(
-        "select * from {}.{}.my_executions where execution_id > {} order by timestamp",
-        project_id, DATASET_ID, latest_pos_exec_id
+        "select * from {} order by timestamp, execution_id",
+        config.table("my_executions")
     );
-    let mut rs = bq_client
-        .job()
-        .query(project_id, QueryRequest::new(query))
-        .await
-        .unwrap();
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
 
-    let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+    // the executions from the start of the current year are not saved by the total method
+    let until = match method {
+        CostMethod::Total => {
+            let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+            let year = Utc::now().with_timezone(&jst).year();
+            let start = jst.with_ymd_and_hms(year, 1, 1, 0, 0, 0).unwrap();
+            println!(
+                "Positions from {} are saved after the year closes",
+                config.local_time(&start.with_timezone(&Utc))
+            );
+            Some(start.with_timezone(&Utc))
+        }
+        _ => None,
+    };
 
-    while rs.next_row() {
-        let ts = rs.get_string_by_name("timestamp").unwrap().unwrap();
-        let exec_id = rs.get_i64_by_name("execution_id").unwrap().unwrap();
-        let size = rs.get_f64_by_name("size").unwrap().unwrap();
-        let side = rs.get_string_by_name("side").unwrap().unwrap();
-
-        if side == "BUY".to_string() {
-            let price = rs.get_f64_by_name("price").unwrap().unwrap();
-            avg_price = (price * size + avg_price * cum_size) / (size + cum_size);
-            cum_size += size;
-        } else {
-            cum_size -= size
+    // symbols to be calculated, or every symbol when empty
+    let symbols = config.job("average_price").symbols;
+
+    let mut seen: HashSet<i64> = HashSet::new();
+    let mut positions: Vec<Positions> = vec![];
+    let mut pnls: Vec<RealizedPnl> = vec![];
+
+    for mut rs in pages {
+        while rs.next_row() {
+            let e = read_my_executions(&rs);
+            if !seen.insert(e.execution_id)
+                || !(symbols.is_empty() || symbols.iter().any(|s| s == e.symbol.as_str()))
+            {
+                continue;
+            }
+            if until.is_some_and(|t| e.timestamp >= t)
+                || starts.get(&e.symbol).is_some_and(|t| e.timestamp < *t)
+            {
+                continue;
+            }
+            let calc = calculators
+                .entry(e.symbol.clone())
+                .or_insert_with(|| PositionCalculator::for_symbol(&e.symbol, method));
+            let (position, pnl) = calculate_position(calc, &e, method);
+            if let Some(pnl) = pnl {
+                if !realized.contains(&e.execution_id) {
+                    pnls.push(pnl);
+                }
+            }
+            if !saved.contains(&e.execution_id) {
+                println!(
+                    "{}, {} {}({}), {}, {:.0}",
+                    config.local_time(&e.timestamp),
+                    e.symbol,
+                    e.execution_id,
+                    e.side,
+                    position.size,
+                    position.average_price
+                );
+                positions.push(position);
+            }
         }
+    }
+    insert_bq_rows(config, &positions, "positions").await;
+    insert_bq_rows(config, &pnls, "realized_pnl").await;
+}
 
-        let s = format!("{:.8}", cum_size).parse::<f64>().unwrap();
-        println!("{}, {}({}), {:.8}, {:.0}", ts, exec_id, side, s, avg_price);
-        ins_req
-            .add_row(
-                None,
-                Positions {
-                    timestamp: ts,
-                    execution_id: exec_id,
-                    average_price: avg_price,
-                    size: s,
-                },
-            )
+// Recalculate the positions and the realized profits by the method from my_executions, and replace
+// the stored ones. With the date (JST), the ones before it are kept and the recalculation starts
+// from the opening positions, or from the last positions kept before it for the other symbols.
+// The positions started from are saved into position_openings, from which average_price
+// continues. With the suffix, the results are written into positions_<suffix> and
+// realized_pnl_<suffix> instead.
+pub async fn rebuild_positions(
+    config: &Config,
+    method: CostMethod,
+    from: Option<NaiveDate>,
+    openings: &[OpeningPosition],
+    suffix: Option<&str>,
+) {
+    let project_id = config.project_id();
+    let (positions_id, pnl_id) = match suffix {
+        Some(v) => (format!("positions_{}", v), format!("realized_pnl_{}", v)),
+        None => (String::from("positions"), String::from("realized_pnl")),
+    };
+    let bq_client = create_bq_client(config).await;
+
+    // create the versioned tables in the same schema
+    if suffix.is_some() {
+        for (table_id, base) in [(&positions_id, "positions"), (&pnl_id, "realized_pnl")] {
+            let query = format!(
+                "create table if not exists {} like {}",
+                config.table(table_id),
+                config.table(base)
+            );
+            bq_client
+                .job()
+                .query(project_id, QueryRequest::new(query))
+                .await
+                .unwrap();
+        }
+    }
+
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let start = from.map(|d| {
+        jst.from_local_datetime(&d.and_hms_opt(0, 0, 0).unwrap())
             .unwrap()
+            .with_timezone(&Utc)
+    });
+
+    // symbols to be calculated, or every symbol when empty
+    let symbols = config.job("average_price").symbols;
+    let mut cond = format!("method = '{}'", method);
+    if !symbols.is_empty() {
+        let list: Vec<String> = symbols.iter().map(|s| format!("'{}'", s)).collect();
+        write!(cond, " and symbol in ({})", list.join(", ")).unwrap();
+    }
+    let mut openings = openings.to_vec();
+    if let Some(t) = start {
+        // symbols without the opening position continue from the last position kept before the
+        // date, of which the fifo lots are merged at the average price
+        let query = format!(
+            "select symbol, size, average_price from {} where {} and timestamp < '{}'
+            qualify row_number() over (partition by symbol order by timestamp desc, execution_id desc) = 1",
+            config.table("positions"),
+            cond,
+            t.format("%Y-%m-%d %H:%M:%S")
+        );
+        let pages = match query_all(&bq_client, project_id, query).await {
+            Ok(v) => v,
+            Err(e) => {
+                println!(
+                    "Failed to read the positions before {}: {:?}",
+                    config.local_time(&t),
+                    e
+                );
+                return;
+            }
+        };
+        for mut rs in pages {
+            while rs.next_row() {
+                let symbol = Symbol::new(&rs.get_string_by_name("symbol").unwrap().unwrap());
+                if !openings.iter().any(|o| o.symbol == symbol) {
+                    openings.push(OpeningPosition {
+                        symbol,
+                        size: get_decimal(&rs, "size"),
+                        price: get_decimal(&rs, "average_price"),
+                    });
+                }
+            }
+        }
+        write!(
+            cond,
+            " and timestamp >= '{}'",
+            t.format("%Y-%m-%d %H:%M:%S")
+        )
+        .unwrap();
+    }
+
+    let mut calculators: HashMap<Symbol, PositionCalculator> = HashMap::new();
+    if let Some(t) = start {
+        for o in &openings {
+            println!(
+                "Opening position of {}: {} at {}",
+                o.symbol, o.size, o.price
+            );
+            calculators
+                .entry(o.symbol.clone())
+                .or_insert_with(|| PositionCalculator::for_symbol(&o.symbol, method))
+                .set_opening(t, o.size, o.price);
+        }
+    }
+
+    // read every execution before deleting anything, so that a failure leaves the stored ones
+    let query = match start {
+        Some(t) => format!(
+            "select * from {} where timestamp >= '{}' order by timestamp, execution_id",
+            config.table("my_executions"),
+            t.format("%Y-%m-%d %H:%M:%S")
+        ),
+        None => format!(
+            "select * from {} order by timestamp, execution_id",
+            config.table("my_executions")
+        ),
+    };
+    let pages = match query_all(&bq_client, project_id, query).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Failed to read my_executions: {:?}", e);
+            return;
+        }
+    };
+
+    let mut seen: HashSet<i64> = HashSet::new();
+    let mut positions: Vec<Positions> = vec![];
+    let mut pnls: Vec<RealizedPnl> = vec![];
+    for mut rs in pages {
+        while rs.next_row() {
+            let e = read_my_executions(&rs);
+            if !seen.insert(e.execution_id)
+                || !(symbols.is_empty() || symbols.iter().any(|s| s == e.symbol.as_str()))
+            {
+                continue;
+            }
+            let calc = calculators
+                .entry(e.symbol.clone())
+                .or_insert_with(|| PositionCalculator::for_symbol(&e.symbol, method));
+            let (position, pnl) = calculate_position(calc, &e, method);
+            positions.push(position);
+            pnls.extend(pnl);
+        }
+    }
+
+    for table_id in [&positions_id, &pnl_id] {
+        let query = format!("delete from {} where {}", config.table(table_id), cond);
+        match execute_dml(&bq_client, project_id, query).await {
+            Ok(n) => println!("Deleted {} rows from {}", n, table_id),
+            Err(e) => {
+                // DML cannot change rows streamed in about the last 90 minutes
+                println!(
+                    "Failed to delete from {}: {:?}. Rows still in the streaming buffer cannot be deleted, so rebuild again once average_price has not run for 90 minutes.",
+                    table_id, e
+                );
+                return;
+            }
+        }
+    }
+
+    println!(
+        "Rebuilt {} positions and {} realized profits by {}",
+        positions.len(),
+        pnls.len(),
+        method
+    );
+    let positions_saved = insert_bq_rows(config, &positions, &positions_id).await;
+    let pnls_saved = insert_bq_rows(config, &pnls, &pnl_id).await;
+    if !(positions_saved && pnls_saved) {
+        // the rebuilt range has been deleted, so leave average_price as it was until it is rebuilt
+        println!(
+            "Failed to save some of the rebuilt rows into {} or {}; run the rebuild again.",
+            positions_id, pnl_id
+        );
+        return;
+    }
+
+    // the versioned tables are not continued by average_price
+    if suffix.is_some() {
+        return;
+    }
+    let rebuilt: Vec<Symbol> = if symbols.is_empty() {
+        calculators.into_keys().collect()
+    } else {
+        symbols.iter().map(|s| Symbol::new(s)).collect()
+    };
+    let rebuilt_at = Utc::now();
+    let rows: Vec<PositionOpenings> = rebuilt
+        .into_iter()
+        .map(|symbol| {
+            let opening = openings
+                .iter()
+                .find(|o| o.symbol == symbol && start.is_some());
+            PositionOpenings {
+                timestamp: start.unwrap_or(DateTime::UNIX_EPOCH),
+                method: method.to_string(),
+                size: opening.map_or(Decimal::ZERO, |o| o.size),
+                price: opening.map_or(Decimal::ZERO, |o| o.price),
+                symbol,
+                rebuilt_at,
+            }
+        })
+        .collect();
+    if !insert_bq_rows(config, &rows, "position_openings").await {
+        println!(
+            "Failed to save the opening positions; average_price does not continue from them."
+        );
+    }
+}
+
+// Apply the execution to the position of its symbol. Return the position after it, and the
+// realized profit when it closes the position.
+fn calculate_position(
+    calc: &mut PositionCalculator,
+    e: &MyExecutions,
+    method: CostMethod,
+) -> (Positions, Option<RealizedPnl>) {
+    let fill = Fill::from(e);
+    let step = calc.apply(&fill);
+    if step.oversold > Decimal::ZERO {
+        println!(
+            "Oversold {} {}: {} more than held is ignored",
+            e.symbol, e.execution_id, step.oversold
+        );
     }
-    insert_bq(ins_req, DATASET_ID, "positions").await;
+
+    // GMO's loss_gain excludes the fee, so it is compared with the profit before the fee. It is
+    // zero for spot trading, where there is nothing to compare with.
+    let pnl = if step.closed_size > Decimal::ZERO {
+        let gross = step.realized;
+        let discrepancy = !e.loss_gain.is_zero() && (gross - e.loss_gain).abs() > PNL_TOLERANCE;
+        if discrepancy {
+            println!(
+                "Discrepancy in {} {}: realized {:.0}, loss_gain {:.0}",
+                e.symbol, e.execution_id, gross, e.loss_gain
+            );
+        }
+        Some(RealizedPnl {
+            timestamp: e.timestamp,
+            execution_id: e.execution_id,
+            symbol: e.symbol.clone(),
+            method: method.to_string(),
+            size: step.closed_size,
+            price: fill.price,
+            proceeds: fill.price * step.closed_size,
+            cost_basis: step.closed_cost,
+            fee: step.closed_fee,
+            realized_pnl: gross - step.closed_fee,
+            loss_gain: e.loss_gain,
+            discrepancy,
+        })
+    } else {
+        None
+    };
+
+    let position = Positions {
+        timestamp: e.timestamp,
+        execution_id: e.execution_id,
+        symbol: e.symbol.clone(),
+        method: method.to_string(),
+        average_price: step.average_price,
+        size: step.size,
+        cost: step.cost,
+    };
+    (position, pnl)
 }
 
-pub async fn get_ticker() {
+// Get ticker of all symbols and save them into BigQuery.
+pub async fn get_ticker(config: &Config) {
     // create GMO API client
-    let gmo = GmoClient::new(
-        env::var("API_KEY").unwrap(),
-        env::var("API_SECRET").unwrap(),
-    );
+    let gmo = create_gmo_client(config);
 
-    // get latest executions by GMO api (within 24 hours)
-    let ticker = &gmo.get_ticker(Some(String::from("BTC"))).await;
+    // symbols to be saved, or every symbol when empty
+    let symbols = config.job("ticker").symbols;
+
+    // get ticker of all symbols at once
+    let ticker = gmo.get_ticker(None).await;
     match ticker {
         Ok(t) => {
-            println!(">> {:?}", t.data);
-            let btc = &t.data[0];
+            if let Some(messages) = t.messages {
+                println!("{:?}", messages);
+            }
+
             let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
-            ins_req
-                .add_row(
-                    None,
-                    Ticker {
-                        timestamp: Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-                        symbol: btc.symbol.clone(),
-                        last: btc.last.parse::<f64>().unwrap(),
-                    },
-                )
-                .unwrap();
-            insert_bq(ins_req, DATASET_ID, "ticker").await;
+            for d in t.data {
+                if symbols.is_empty() || symbols.contains(&d.symbol) {
+                    let ticker = convert_ticker(&d);
+                    println!(
+                        "Ticker: {} {} {}",
+                        config.local_time(&ticker.timestamp),
+                        ticker.symbol,
+                        ticker.last
+                    );
+                    ins_req.add_row(None, ticker).unwrap();
+                }
+            }
+            insert_bq(config, ins_req, "ticker").await;
         }
         Err(e) => {
             println!("err>{}", e);
         }
     }
 }
+
+fn convert_ticker(t: &TickerData) -> Ticker {
+    Ticker {
+        timestamp: parse_gmo_timestamp(&t.timestamp),
+        symbol: t.symbol.clone(),
+        ask: t.ask.parse::<Decimal>().unwrap(),
+        bid: t.bid.parse::<Decimal>().unwrap(),
+        high: t.high.parse::<Decimal>().unwrap(),
+        low: t.low.parse::<Decimal>().unwrap(),
+        last: t.last.parse::<Decimal>().unwrap(),
+        volume: t.volume.parse::<Decimal>().unwrap(),
+    }
+}
+
+// Get deposit and withdrawal history of JPY and crypto and save new ones into BigQuery.
+pub async fn get_transfers(config: &Config) {
+    // select latest transfer timestamp from BigQuery
+    let project_id = config.project_id();
+    let table_id = "transfers";
+    let query = format!(
+        "select max(timestamp) as timestamp from {}",
+        config.table(table_id)
+    );
+
+    let bq_client = create_bq_client(config).await;
+    let mut rs = bq_client
+        .job()
+        .query(project_id, QueryRequest::new(query))
+        .await
+        .unwrap();
+
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let job = config.job("transfers");
+    let mut from = match job.start {
+        Some(d) => jst
+            .from_local_datetime(&d.and_hms_opt(0, 0, 0).unwrap())
+            .unwrap()
+            .with_timezone(&Utc),
+        None => DateTime::parse_from_rfc3339(TRANSFERS_START)
+            .unwrap()
+            .with_timezone(&Utc),
+    };
+    if rs.next_row() {
+        if let Some(v) = rs.get_string_by_name("timestamp").unwrap() {
+            from = parse_bq_timestamp(&v);
+        }
+    } else {
+        println!("There are no past records.");
+    }
+    println!("Latest transfer timestamp: {}", config.local_time(&from));
+
+    // transfers at the latest timestamp are returned again, so remember what is already stored
+    let query = format!(
+        "select transaction_id from {} where timestamp >= '{}'",
+        config.table(table_id),
+        from.format("%Y-%m-%d %H:%M:%S")
+    );
+    let mut rs = bq_client
+        .job()
+        .query(project_id, QueryRequest::new(query))
+        .await
+        .unwrap();
+    let mut transaction_ids: HashSet<String> = HashSet::new();
+    while rs.next_row() {
+        if let Some(v) = rs.get_string_by_name("transaction_id").unwrap() {
+            transaction_ids.insert(v);
+        }
+    }
+
+    // create GMO API client
+    let gmo = create_gmo_client(config);
+
+    // crypto history is requested per symbol, so use every symbol of the account unless they are
+    // configured
+    let mut symbols = job.symbols;
+    if symbols.is_empty() {
+        symbols = gmo
+            .get_assets()
+            .await
+            .unwrap()
+            .data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| a.symbol)
+            .collect();
+    }
+    symbols.retain(|s| s != "JPY");
+
+    let now = Utc::now();
+    while from < now {
+        let to = now.min(from + chrono::Duration::days(TRANSFERS_WINDOW_DAYS));
+        println!(
+            "Search transfers: {} - {}",
+            config.local_time(&from),
+            config.local_time(&to)
+        );
+
+        let mut responses = vec![
+            ("DEPOSIT", gmo.get_fiat_deposit_history(from, to).await),
+            (
+                "WITHDRAWAL",
+                gmo.get_fiat_withdrawal_history(from, to).await,
+            ),
+        ];
+        for symbol in &symbols {
+            responses.push((
+                "DEPOSIT",
+                gmo.get_deposit_history(symbol.clone(), from, to).await,
+            ));
+            responses.push((
+                "WITHDRAWAL",
+                gmo.get_withdrawal_history(symbol.clone(), from, to).await,
+            ));
+        }
+
+        // the next run resumes from the latest stored transfer, so each window is saved as soon as
+        // every request of it succeeded, and the windows after a failure are left to the next run
+        let mut rows = vec![];
+        let mut failed = false;
+        for (transfer_type, res) in responses {
+            match res {
+                Ok(TransfersResponse {
+                    data: Some(list), ..
+                }) => {
+                    for t in list {
+                        rows.push(convert_transfers(transfer_type, &t));
+                    }
+                }
+                Ok(TransfersResponse {
+                    messages: Some(messages),
+                    ..
+                }) => {
+                    println!("{:?}", messages);
+                    failed = true;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    println!("{:?}", e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            println!("Stopped at the failed window; it is retried by the next run.");
+            break;
+        }
+        let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+        for row in rows {
+            if transaction_ids.insert(row.transaction_id.clone()) {
+                println!(
+                    "Found new transfer: id={}, timestamp={}, type={}, symbol={}, amount={}",
+                    row.transaction_id,
+                    config.local_time(&row.timestamp),
+                    row.transfer_type,
+                    row.symbol,
+                    row.amount
+                );
+                ins_req.add_row(None, row).unwrap();
+            }
+        }
+        if !ins_req.is_empty() && !insert_bq(config, ins_req, table_id).await {
+            println!("Stopped at the window failed to be saved; it is retried by the next run.");
+            break;
+        }
+        from = to;
+    }
+}
+
+fn convert_transfers(transfer_type: &str, t: &Transfer) -> Transfers {
+    // fiat transfers have no transaction hash, so identify them by their contents
+    let transaction_id = match &t.tx_hash {
+        Some(v) => v.clone(),
+        None => format!("{}-{}-{}", transfer_type, t.symbol, t.timestamp),
+    };
+
+    Transfers {
+        transaction_id,
+        transfer_type: transfer_type.to_string(),
+        symbol: t.symbol.clone(),
+        amount: t.amount.parse::<Decimal>().unwrap(),
+        fee: t
+            .fee
+            .as_ref()
+            .map_or(Decimal::ZERO, |v| v.parse::<Decimal>().unwrap()),
+        status: t.status.clone(),
+        address: t.address.clone(),
+        timestamp: parse_gmo_timestamp(&t.timestamp),
+    }
+}
+
+// Timestamps of the API are in RFC 3339 with milliseconds, such as "2019-03-19T02:15:06.059Z".
+fn parse_gmo_timestamp(v: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(v).unwrap().with_timezone(&Utc)
+}
+
+// Get klines of the configured symbols and intervals and save new ones into BigQuery. Without the
+// date range, collection resumes from the last stored candle of each symbol and interval.
+pub async fn get_klines(config: &Config, from: Option<NaiveDate>, to: Option<NaiveDate>) {
+    // select the last candle of each symbol and interval from BigQuery
+    let project_id = config.project_id();
+    let table_id = "klines";
+    let query = format!(
+        "select symbol, `interval`, max(open_time) as open_time from {} group by symbol, `interval`",
+        config.table(table_id)
+    );
+
+    let bq_client = create_bq_client(config).await;
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+    let mut latest: HashMap<(String, String), DateTime<Utc>> = HashMap::new();
+    for mut rs in pages {
+        while rs.next_row() {
+            let symbol = rs.get_string_by_name("symbol").unwrap().unwrap();
+            let interval = rs.get_string_by_name("interval").unwrap().unwrap();
+            if let Some(v) = rs.get_string_by_name("open_time").unwrap() {
+                latest.insert((symbol, interval), parse_bq_timestamp(&v));
+            }
+        }
+    }
+
+    // create GMO API client
+    let gmo = create_gmo_client(config);
+
+    let now = Utc::now();
+    let today = kline_date(now);
+
+    let job = config.job("klines");
+    for symbol in &job.symbols {
+        for interval in &job.intervals {
+            // resume from the day of the last stored candle
+            let start = match (from, latest.get(&(symbol.clone(), interval.clone()))) {
+                (Some(d), _) => d,
+                (None, Some(t)) => kline_date(*t),
+                (None, None) => today,
+            };
+            let end = to.unwrap_or(today);
+            let lower = kline_day_start(start);
+            println!("Klines {} {}: {} - {}", symbol, interval, start, end);
+
+            // candles already stored in the range
+            let query = format!(
+                "select open_time from {} where symbol = '{}' and `interval` = '{}' and open_time >= '{}'",
+                config.table(table_id),
+                symbol,
+                interval,
+                lower.format("%Y-%m-%d %H:%M:%S")
+            );
+            let pages = query_all(&bq_client, project_id, query).await.unwrap();
+            let mut stored: HashSet<DateTime<Utc>> = HashSet::new();
+            for mut rs in pages {
+                while rs.next_row() {
+                    if let Some(v) = rs.get_string_by_name("open_time").unwrap() {
+                        stored.insert(parse_bq_timestamp(&v));
+                    }
+                }
+            }
+
+            for date in kline_dates(interval, start, end) {
+                let mut ins_req: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+                match gmo
+                    .get_klines(symbol.clone(), interval.clone(), date.clone())
+                    .await
+                {
+                    Ok(KlinesResponse {
+                        data: Some(list), ..
+                    }) => {
+                        for (i, k) in list.iter().enumerate() {
+                            let open_time = Utc
+                                .timestamp_millis_opt(k.open_time.parse::<i64>().unwrap())
+                                .unwrap();
+                            // the last candle may be still open
+                            let closed =
+                                i + 1 < list.len() || kline_closed(interval, open_time, now);
+                            if open_time >= lower && closed && stored.insert(open_time) {
+                                ins_req
+                                    .add_row(None, convert_klines(symbol, interval, open_time, k))
+                                    .unwrap();
+                            }
+                        }
+                    }
+                    Ok(KlinesResponse {
+                        messages: Some(messages),
+                        ..
+                    }) => {
+                        println!("{:?}", messages)
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("{:?}", e)
+                    }
+                }
+                println!("  {}: {} candles", date, ins_req.len());
+                insert_bq(config, ins_req, table_id).await;
+            }
+        }
+    }
+}
+
+// Date of GMO's klines which the time belongs to. A day of klines starts at 6:00 JST.
+fn kline_date(t: DateTime<Utc>) -> NaiveDate {
+    let offset = FixedOffset::east_opt(JST_OFFSET_SECS - KLINE_DAY_START_HOURS * 60 * 60).unwrap();
+    t.with_timezone(&offset).date_naive()
+}
+
+// Time when the day of GMO's klines starts.
+fn kline_day_start(date: NaiveDate) -> DateTime<Utc> {
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    jst.from_local_datetime(
+        &date
+            .and_hms_opt(KLINE_DAY_START_HOURS as u32, 0, 0)
+            .unwrap(),
+    )
+    .unwrap()
+    .with_timezone(&Utc)
+}
+
+// Dates to request klines of the interval from one date to another in JST.
+fn kline_dates(interval: &str, from: NaiveDate, to: NaiveDate) -> Vec<String> {
+    if DAILY_KLINE_INTERVALS.contains(&interval) {
+        from.iter_days()
+            .take_while(|d| *d <= to)
+            .map(|d| d.format("%Y%m%d").to_string())
+            .collect()
+    } else {
+        (from.year()..=to.year()).map(|y| y.to_string()).collect()
+    }
+}
+
+// Whether the candle which opened at the time has been closed. A month is regarded as 31 days.
+fn kline_closed(interval: &str, open_time: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    let minutes = match interval {
+        "1min" => 1,
+        "5min" => 5,
+        "10min" => 10,
+        "15min" => 15,
+        "30min" => 30,
+        "1hour" => 60,
+        "4hour" => 4 * 60,
+        "8hour" => 8 * 60,
+        "12hour" => 12 * 60,
+        "1day" => 24 * 60,
+        "1week" => 7 * 24 * 60,
+        _ => 31 * 24 * 60,
+    };
+    open_time + chrono::Duration::minutes(minutes) <= now
+}
+
+fn convert_klines(symbol: &str, interval: &str, open_time: DateTime<Utc>, k: &Kline) -> Klines {
+    Klines {
+        symbol: symbol.to_string(),
+        interval: interval.to_string(),
+        open_time,
+        open: k.open.parse::<Decimal>().unwrap(),
+        high: k.high.parse::<Decimal>().unwrap(),
+        low: k.low.parse::<Decimal>().unwrap(),
+        close: k.close.parse::<Decimal>().unwrap(),
+        volume: k.volume.parse::<Decimal>().unwrap(),
+    }
+}
+
+// Read NUMERIC value from BigQuery, which is returned as a string. Values saved as float in
+// STRING columns may be in scientific notation.
+fn get_decimal(rs: &ResultSet, name: &str) -> Decimal {
+    let v = rs.get_string_by_name(name).unwrap().unwrap();
+    Decimal::from_str(&v)
+        .or_else(|_| Decimal::from_scientific(&v))
+        .unwrap()
+}
+
+// Parse TIMESTAMP value returned from BigQuery, which is seconds since the epoch such as
+// "1.552961706059E9". DATETIME values of the tables created before are read as UTC.
+fn parse_bq_timestamp(v: &str) -> DateTime<Utc> {
+    if let Ok(t) = NaiveDateTime::parse_from_str(v, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Utc.from_utc_datetime(&t);
+    }
+    let secs = Decimal::from_str(v)
+        .or_else(|_| Decimal::from_scientific(v))
+        .unwrap();
+    let micros = i64::try_from((secs * Decimal::from(1_000_000)).round()).unwrap();
+    Utc.timestamp_micros(micros).unwrap()
+}
+
+// Get order book snapshots of the configured symbols and save the top levels and the depth
+// metrics into BigQuery.
+pub async fn get_orderbooks(config: &Config) {
+    // create GMO API client
+    let gmo = create_gmo_client(config);
+
+    let job = config.job("orderbook");
+    let mut books: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+    let mut metrics: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+    for symbol in &job.symbols {
+        match gmo.get_orderbooks(symbol.clone()).await {
+            Ok(OrderbooksResponse {
+                data: Some(book), ..
+            }) => {
+                add_orderbook(config, &mut books, &mut metrics, &book, job.levels);
+            }
+            Ok(OrderbooksResponse {
+                messages: Some(messages),
+                ..
+            }) => {
+                println!("{:?}", messages)
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("{:?}", e)
+            }
+        }
+    }
+
+    insert_bq(config, books, "orderbooks").await;
+    insert_bq(config, metrics, "orderbook_metrics").await;
+}
+
+// Receive order books of the configured symbols from the public WebSocket API and save a snapshot
+// of each symbol every interval.
+pub async fn stream_orderbooks(config: &Config, interval: Duration) {
+    let job = config.job("orderbook");
+    let mut ws = PublicWsClient::connect();
+    for symbol in &job.symbols {
+        ws.subscribe(PublicChannel::Orderbooks, symbol);
+    }
+
+    let mut saved_at: HashMap<String, tokio::time::Instant> = HashMap::new();
+    while let Some(event) = ws.next().await {
+        if let PublicEvent::Orderbooks(book) = event {
+            let now = tokio::time::Instant::now();
+            if saved_at
+                .get(&book.symbol)
+                .is_some_and(|t| now < *t + interval)
+            {
+                continue;
+            }
+            saved_at.insert(book.symbol.clone(), now);
+
+            let mut books: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+            let mut metrics: TableDataInsertAllRequest = TableDataInsertAllRequest::new();
+            add_orderbook(config, &mut books, &mut metrics, &book, job.levels);
+            insert_bq(config, books, "orderbooks").await;
+            insert_bq(config, metrics, "orderbook_metrics").await;
+        }
+    }
+}
+
+// Add the top levels of the order book and its metrics to the requests.
+fn add_orderbook(
+    config: &Config,
+    books: &mut TableDataInsertAllRequest,
+    metrics: &mut TableDataInsertAllRequest,
+    book: &Orderbook,
+    levels: usize,
+) {
+    let timestamp = parse_gmo_timestamp(&book.timestamp);
+
+    let asks = parse_levels(&book.asks);
+    let bids = parse_levels(&book.bids);
+    for (side, list) in [("ASK", &asks), ("BID", &bids)] {
+        for (i, (price, size)) in list.iter().take(levels).enumerate() {
+            let row = Orderbooks {
+                timestamp,
+                symbol: book.symbol.clone(),
+                side: side.to_string(),
+                level: i + 1,
+                price: *price,
+                size: *size,
+            };
+            books.add_row(None, row).unwrap();
+        }
+    }
+
+    if let Some(m) = orderbook_metrics(timestamp, &book.symbol, &asks, &bids) {
+        println!(
+            "Orderbook: {} {} spread={} mid={} depth(0.5%)={}/{}",
+            config.local_time(&m.timestamp),
+            m.symbol,
+            m.spread,
+            m.mid_price,
+            m.bid_depth_0_5,
+            m.ask_depth_0_5
+        );
+        metrics.add_row(None, m).unwrap();
+    }
+}
+
+// Spread, mid price and depth within 0.5% and 1% of the mid price. Metrics need both sides of the
+// book.
+fn orderbook_metrics(
+    timestamp: DateTime<Utc>,
+    symbol: &str,
+    asks: &[(Decimal, Decimal)],
+    bids: &[(Decimal, Decimal)],
+) -> Option<OrderbookMetrics> {
+    let ((best_ask, _), (best_bid, _)) = (asks.first()?, bids.first()?);
+    let mid_price = (best_ask + best_bid) / Decimal::TWO;
+    let (half, one) = (Decimal::new(5, 3), Decimal::new(1, 2));
+    Some(OrderbookMetrics {
+        timestamp,
+        symbol: symbol.to_string(),
+        best_bid: *best_bid,
+        best_ask: *best_ask,
+        spread: best_ask - best_bid,
+        mid_price,
+        bid_depth_0_5: depth(bids, mid_price * (Decimal::ONE - half)..=mid_price),
+        ask_depth_0_5: depth(asks, mid_price..=mid_price * (Decimal::ONE + half)),
+        bid_depth_1: depth(bids, mid_price * (Decimal::ONE - one)..=mid_price),
+        ask_depth_1: depth(asks, mid_price..=mid_price * (Decimal::ONE + one)),
+    })
+}
+
+fn parse_levels(levels: &[OrderbookLevel]) -> Vec<(Decimal, Decimal)> {
+    levels
+        .iter()
+        .map(|l| {
+            (
+                l.price.parse::<Decimal>().unwrap(),
+                l.size.parse::<Decimal>().unwrap(),
+            )
+        })
+        .collect()
+}
+
+// Cumulative size of the levels whose prices are within the range.
+fn depth(levels: &[(Decimal, Decimal)], range: RangeInclusive<Decimal>) -> Decimal {
+    levels
+        .iter()
+        .filter(|(p, _)| range.contains(p))
+        .map(|(_, s)| s)
+        .sum()
+}
+
+// Get public trades of the configured symbols after the last stored ones and save them into
+// BigQuery.
+pub async fn get_trades(config: &Config) {
+    // select the last trade time of each symbol from BigQuery
+    let project_id = config.project_id();
+    let table_id = "market_trades";
+    let query = format!(
+        "select symbol, max(timestamp) as timestamp from {} group by symbol",
+        config.table(table_id)
+    );
+
+    let bq_client = create_bq_client(config).await;
+    let mut rs = bq_client
+        .job()
+        .query(project_id, QueryRequest::new(query))
+        .await
+        .unwrap();
+    let mut latest: HashMap<String, DateTime<Utc>> = HashMap::new();
+    while rs.next_row() {
+        let symbol = rs.get_string_by_name("symbol").unwrap().unwrap();
+        if let Some(v) = rs.get_string_by_name("timestamp").unwrap() {
+            latest.insert(symbol, parse_bq_timestamp(&v));
+        }
+    }
+
+    // create GMO API client
+    let gmo = create_gmo_client(config);
+
+    for symbol in &config.job("trades").symbols {
+        let last = latest.get(symbol).copied();
+        match last {
+            Some(t) => println!(
+                "Trades {}: last stored at {}",
+                symbol,
+                config.local_time(&t)
+            ),
+            None => println!("Trades {}: nothing stored", symbol),
+        }
+
+        // trades are listed from the newest, so read pages until the stored ones appear
+        let mut trades: Vec<MarketTrades> = vec![];
+        let mut reached = last.is_none();
+        'pages: for page in 1..=TRADES_MAX_PAGES {
+            match gmo
+                .get_trades(symbol.clone(), Some(page), Some(TRADES_PAGE_SIZE))
+                .await
+            {
+                Ok(TradesResponse {
+                    data:
+                        Some(TradesData {
+                            list: Some(list), ..
+                        }),
+                    ..
+                }) => {
+                    if list.is_empty() {
+                        reached = true;
+                        break;
+                    }
+                    for t in &list {
+                        let trade = convert_market_trades(symbol, t);
+                        if last.is_some_and(|l| trade.timestamp <= l) {
+                            reached = true;
+                            break 'pages;
+                        }
+                        trades.push(trade);
+                    }
+                }
+                Ok(TradesResponse {
+                    messages: Some(messages),
+                    ..
+                }) => {
+                    println!("{:?}", messages);
+                    break;
+                }
+                Ok(_) => break,
+                Err(e) => {
+                    println!("{:?}", e);
+                    break;
+                }
+            }
+        }
+        if !reached {
+            println!(
+                "  trades older than {} pages were not read; there may be a gap.",
+                TRADES_MAX_PAGES
+            );
+        }
+
+        // save from the oldest
+        trades.reverse();
+        println!("  {} new trades", trades.len());
+        insert_bq_rows(config, &trades, table_id).await;
+    }
+}
+
+fn convert_market_trades(symbol: &str, t: &Trade) -> MarketTrades {
+    MarketTrades {
+        symbol: symbol.to_string(),
+        side: t.side.clone(),
+        price: t.price.parse::<Decimal>().unwrap(),
+        size: t.size.parse::<Decimal>().unwrap(),
+        timestamp: parse_gmo_timestamp(&t.timestamp),
+    }
+}
+
+// Import the daily public trade archives of the configured symbols into BigQuery. Each archive
+// file is read from the directory when it exists there, and otherwise downloaded from GMO and
+// kept in the directory. Days already imported are skipped, and only the trades not stored yet
+// are added to partially stored days, which the trades job may still be streaming into. Trades are
+// streamed with one client, since load jobs need the files in Cloud Storage, and the days failed
+// to be saved are reported at the end to be imported again.
+pub async fn import_trade_archive(
+    config: &Config,
+    from: NaiveDate,
+    to: Option<NaiveDate>,
+    dir: Option<&str>,
+) {
+    let project_id = config.project_id();
+    let table_id = "market_trades";
+    let bq_client = create_bq_client(config).await;
+    let gmo = create_gmo_client(config);
+
+    // archives are published on the next day
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let end = to.unwrap_or(Utc::now().with_timezone(&jst).date_naive() - chrono::Duration::days(1));
+    let lower = jst
+        .from_local_datetime(&from.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap()
+        .with_timezone(&Utc);
+    let upper = lower + chrono::Duration::days((end - from).num_days() + 1);
+
+    let mut failed: Vec<String> = vec![];
+    for symbol in &config.job("trades").symbols {
+        println!("Trade archive {}: {} - {}", symbol, from, end);
+
+        // number of trades already stored on each day in JST
+        let query = format!(
+            "select date(timestamp, 'Asia/Tokyo') as date, count(*) as count from {} where symbol = '{}' and timestamp >= '{}' and timestamp < '{}' group by date",
+            config.table(table_id),
+            symbol,
+            lower.format("%Y-%m-%d %H:%M:%S"),
+            upper.format("%Y-%m-%d %H:%M:%S")
+        );
+        let pages = query_all(&bq_client, project_id, query).await.unwrap();
+        let mut stored: HashMap<NaiveDate, usize> = HashMap::new();
+        for mut rs in pages {
+            while rs.next_row() {
+                let date = rs.get_string_by_name("date").unwrap().unwrap();
+                let count = rs.get_i64_by_name("count").unwrap().unwrap();
+                stored.insert(
+                    NaiveDate::parse_from_str(&date, "%Y-%m-%d").unwrap(),
+                    count as usize,
+                );
+            }
+        }
+
+        for date in from.iter_days().take_while(|d| *d <= end) {
+            let file_name = GmoClient::trade_archive_file_name(symbol, date);
+            let local = dir.map(|d| Path::new(d).join(&file_name));
+            let gz = match &local {
+                Some(path) if path.exists() => fs::read(path).unwrap(),
+                _ => match gmo.get_trade_archive(symbol, date).await {
+                    Ok(Some(gz)) => {
+                        if let Some(path) = &local {
+                            fs::write(path, &gz).unwrap();
+                        }
+                        gz
+                    }
+                    Ok(None) => {
+                        println!("  {}: not published", file_name);
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("  {}: {:?}", file_name, e);
+                        continue;
+                    }
+                },
+            };
+
+            let mut trades = match read_trade_archive(&gz) {
+                Ok(trades) => trades,
+                Err(e) => {
+                    println!("  {}: {}", file_name, e);
+                    continue;
+                }
+            };
+            let count = stored.get(&date).copied().unwrap_or(0);
+            if count >= trades.len() {
+                println!("  {}: already imported", file_name);
+                continue;
+            }
+
+            // trades have no ID, so leave out the ones of the day saved by the trades job by
+            // their contents
+            if count > 0 {
+                let start = jst
+                    .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                    .unwrap()
+                    .with_timezone(&Utc);
+                let query = format!(
+                    "select side, price, size, timestamp from {} where symbol = '{}' and timestamp >= '{}' and timestamp < '{}'",
+                    config.table(table_id),
+                    symbol,
+                    start.format("%Y-%m-%d %H:%M:%S"),
+                    (start + chrono::Duration::days(1)).format("%Y-%m-%d %H:%M:%S")
+                );
+                let pages = match query_all(&bq_client, project_id, query).await {
+                    Ok(pages) => pages,
+                    Err(e) => {
+                        println!("  {}: {:?}", file_name, e);
+                        continue;
+                    }
+                };
+                let mut existing: HashMap<(String, String, String, DateTime<Utc>), usize> =
+                    HashMap::new();
+                for mut rs in pages {
+                    while rs.next_row() {
+                        let side = rs.get_string_by_name("side").unwrap().unwrap();
+                        let ts = rs.get_string_by_name("timestamp").unwrap().unwrap();
+                        let key = (
+                            side,
+                            get_decimal(&rs, "price").normalize().to_string(),
+                            get_decimal(&rs, "size").normalize().to_string(),
+                            parse_bq_timestamp(&ts),
+                        );
+                        *existing.entry(key).or_default() += 1;
+                    }
+                }
+                trades.retain(|t| {
+                    let key = (
+                        t.side.clone(),
+                        t.price.normalize().to_string(),
+                        t.size.normalize().to_string(),
+                        t.timestamp,
+                    );
+                    match existing.get_mut(&key) {
+                        Some(n) if *n > 0 => {
+                            *n -= 1;
+                            false
+                        }
+                        _ => true,
+                    }
+                });
+                println!("  {}: {} trades already stored", file_name, count);
+            }
+
+            println!("  {}: {} new trades", file_name, trades.len());
+            if !insert_bq_rows_with(&bq_client, config, &trades, table_id).await {
+                failed.push(file_name);
+            }
+        }
+    }
+    if !failed.is_empty() {
+        println!(
+            "Failed to save {} days, which are completed by importing them again: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+}
+
+// Decompress and parse a daily trade archive. Timestamps are converted from JST into UTC.
+fn read_trade_archive(gz: &[u8]) -> Result<Vec<MarketTrades>, String> {
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let mut reader = csv::Reader::from_reader(GzDecoder::new(gz));
+    let mut trades = vec![];
+    for r in reader.deserialize::<TradeArchiveRecord>() {
+        let r = r.map_err(|e| e.to_string())?;
+        let t = NaiveDateTime::parse_from_str(&r.timestamp, "%Y-%m-%d %H:%M:%S%.f")
+            .map_err(|e| format!("{}: {}", r.timestamp, e))?;
+        let timestamp = jst.from_local_datetime(&t).unwrap().with_timezone(&Utc);
+        trades.push(MarketTrades {
+            symbol: r.symbol,
+            side: r.side,
+            price: r.price.parse::<Decimal>().map_err(|e| e.to_string())?,
+            size: r.size.parse::<Decimal>().map_err(|e| e.to_string())?,
+            timestamp,
+        });
+    }
+    Ok(trades)
+}
+
+// Build candles with VWAP of the configured symbols and intervals from market_trades. Candles
+// from the lookback before the last stored one, or from the given date, are recomputed and merged
+// so that late trades are reflected. Candles open at multiples of the interval since the Unix
+// epoch in UTC.
+pub async fn aggregate(config: &Config, from: Option<NaiveDate>, lookback: Option<i64>) {
+    // select the last candle of each symbol and interval from BigQuery
+    let project_id = config.project_id();
+    let table_id = "candles";
+    let query = format!(
+        "select symbol, `interval`, max(open_time) as open_time from {} group by symbol, `interval`",
+        config.table(table_id)
+    );
+
+    let bq_client = create_bq_client(config).await;
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+    let mut latest: HashMap<(String, String), DateTime<Utc>> = HashMap::new();
+    for mut rs in pages {
+        while rs.next_row() {
+            let symbol = rs.get_string_by_name("symbol").unwrap().unwrap();
+            let interval = rs.get_string_by_name("interval").unwrap().unwrap();
+            if let Some(v) = rs.get_string_by_name("open_time").unwrap() {
+                latest.insert((symbol, interval), parse_bq_timestamp(&v));
+            }
+        }
+    }
+
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let lookback = chrono::Duration::seconds(lookback.unwrap_or(AGGREGATE_LOOKBACK_SECS));
+
+    let job = config.job("aggregate");
+    for symbol in &job.symbols {
+        for interval in &job.intervals {
+            let secs = match parse_interval(interval) {
+                Some(v) => v,
+                None => {
+                    println!("Invalid interval: {}", interval);
+                    continue;
+                }
+            };
+
+            // recompute all candles when nothing is stored yet
+            let start = match (from, latest.get(&(symbol.clone(), interval.clone()))) {
+                (Some(d), _) => Some(
+                    jst.from_local_datetime(&d.and_hms_opt(0, 0, 0).unwrap())
+                        .unwrap()
+                        .with_timezone(&Utc),
+                ),
+                (None, Some(t)) => Some(*t - lookback),
+                (None, None) => None,
+            };
+            let start = start.map(|t| {
+                let ts = t.timestamp();
+                Utc.timestamp_opt(ts - ts.rem_euclid(secs), 0).unwrap()
+            });
+            match start {
+                Some(t) => println!(
+                    "Candles {} {}: from {}",
+                    symbol,
+                    interval,
+                    config.local_time(&t)
+                ),
+                None => println!("Candles {} {}: from the first trade", symbol, interval),
+            }
+
+            let trades_cond = match start {
+                Some(t) => format!(" and timestamp >= '{}'", t.format("%Y-%m-%d %H:%M:%S")),
+                None => String::new(),
+            };
+            // merge instead of delete and insert so that a failure leaves the stored candles as they are
+            let query = format!(
+                "merge {table} c
+                using (
+                  select
+                    symbol,
+                    '{interval}' as `interval`,
+                    open_time,
+                    array_agg(price order by timestamp limit 1)[offset(0)] as open,
+                    max(price) as high,
+                    min(price) as low,
+                    array_agg(price order by timestamp desc limit 1)[offset(0)] as close,
+                    sum(size) as volume,
+                    round(sum(price * size) / sum(size), 9) as vwap,
+                    count(*) as trades
+                  from (
+                    select
+                      symbol, price, size, timestamp,
+                      timestamp_seconds(div(unix_seconds(timestamp), {secs}) * {secs}) as open_time
+                    from {trades}
+                    where symbol = '{symbol}'{trades_cond}
+                  )
+                  group by symbol, open_time
+                ) n
+                on c.symbol = n.symbol and c.`interval` = n.`interval` and c.open_time = n.open_time
+                when matched then update set
+                  open = n.open, high = n.high, low = n.low, close = n.close,
+                  volume = n.volume, vwap = n.vwap, trades = n.trades
+                when not matched then insert (symbol, `interval`, open_time, open, high, low, close, volume, vwap, trades)
+                  values (n.symbol, n.`interval`, n.open_time, n.open, n.high, n.low, n.close, n.volume, n.vwap, n.trades)",
+                table = config.table(table_id),
+                trades = config.table("market_trades"),
+            );
+            match execute_dml(&bq_client, project_id, query).await {
+                Ok(n) => println!("  merged {} candles", n),
+                Err(e) => println!(
+                    "  failed to merge candles: {:?} (candles streamed recently cannot be updated; retried by the next run)",
+                    e
+                ),
+            }
+        }
+    }
+}
+
+// Write the yearly summary of each spot symbol for the tax return as CSV and Markdown files in the
+// directory. Completed deposits and withdrawals in the transfers table are taken into account when
+// it exists. The settlements of leverage trading are totaled in a section of the Markdown.
+pub async fn tax_report(config: &Config, year: i32, method: CostMethod, out_dir: &str) {
+    if method == CostMethod::Fifo {
+        println!("Note: fifo is not accepted for the tax return in Japan.");
+    }
+
+    // every event until the end of the year in JST
+    let project_id = config.project_id();
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let end = jst
+        .with_ymd_and_hms(year + 1, 1, 1, 0, 0, 0)
+        .unwrap()
+        .naive_utc()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    let query = format!(
+        "select * from {} where timestamp < '{}' order by timestamp, execution_id",
+        config.table("my_executions"),
+        end
+    );
+
+    let bq_client = create_bq_client(config).await;
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+
+    let mut events: BTreeMap<String, Vec<TaxEvent>> = BTreeMap::new();
+    let mut leverage: BTreeMap<String, LeverageSummary> = BTreeMap::new();
+    let mut seen: HashSet<i64> = HashSet::new();
+    for mut rs in pages {
+        while rs.next_row() {
+            let e = read_my_executions(&rs);
+            if !seen.insert(e.execution_id) {
+                continue;
+            }
+            // leverage trading such as BTC_JPY is not part of the holdings, and is totaled apart
+            if e.symbol.is_leverage() {
+                if e.timestamp.with_timezone(&jst).year() == year {
+                    let l =
+                        leverage
+                            .entry(e.symbol.to_string())
+                            .or_insert_with(|| LeverageSummary {
+                                symbol: e.symbol.to_string(),
+                                ..Default::default()
+                            });
+                    l.loss_gain += e.loss_gain;
+                    l.fee += e.fee;
+                }
+                continue;
+            }
+            events
+                .entry(e.symbol.to_string())
+                .or_default()
+                .push(TaxEvent::Trade(Fill::from(&e)));
+        }
+    }
+
+    let query = format!(
+        "select symbol, transfer_type, amount, timestamp from {} where symbol != 'JPY' and status = '{}' and timestamp < '{}' order by timestamp",
+        config.table("transfers"),
+        TRANSFER_EXECUTED,
+        end
+    );
+    match query_all(&bq_client, project_id, query).await {
+        Ok(pages) => {
+            for mut rs in pages {
+                while rs.next_row() {
+                    let symbol = rs.get_string_by_name("symbol").unwrap().unwrap();
+                    let ts = rs.get_string_by_name("timestamp").unwrap().unwrap();
+                    let t = parse_bq_timestamp(&ts);
+                    let amount = get_decimal(&rs, "amount");
+                    let e = match rs.get_string_by_name("transfer_type").unwrap().as_deref() {
+                        Some("DEPOSIT") => {
+                            println!(
+                                "Warning: deposit of {} {} at {} is regarded as acquired at zero cost.",
+                                amount,
+                                symbol,
+                                config.local_time(&t)
+                            );
+                            TaxEvent::Deposit(t, amount)
+                        }
+                        _ => TaxEvent::Withdrawal(t, amount),
+                    };
+                    events.entry(symbol).or_default().push(e);
+                }
+            }
+        }
+        Err(e) => println!("Transfers are not taken into account: {:?}", e),
+    }
+    for list in events.values_mut() {
+        list.sort_by_key(|e| e.timestamp());
+    }
+
+    let summaries = tax::summarize(year, method, &events);
+    let leverage: Vec<LeverageSummary> = leverage.into_values().collect();
+    for l in &leverage {
+        println!(
+            "Warning: leverage trading of {} is not in the worksheet: loss_gain {}, fee {}",
+            l.symbol, l.loss_gain, l.fee
+        );
+    }
+    let base = Path::new(out_dir).join(format!("tax_report_{}_{}", year, method));
+
+    let csv_path = base.with_extension("csv");
+    tax::write_csv(fs::File::create(&csv_path).unwrap(), &summaries).unwrap();
+    println!("Wrote {}", csv_path.display());
+
+    let md_path = base.with_extension("md");
+    fs::write(
+        &md_path,
+        tax::to_markdown(year, method, &summaries, &leverage),
+    )
+    .unwrap();
+    println!("Wrote {}", md_path.display());
+}
+
+// Export spot executions in the format of a tax service. Without the year, every execution is
+// exported.
+pub async fn export_tax_csv(
+    config: &Config,
+    format: ExportFormat,
+    year: Option<i32>,
+    output: Option<&str>,
+) {
+    let project_id = config.project_id();
+    let jst = FixedOffset::east_opt(JST_OFFSET_SECS).unwrap();
+    let range = match year {
+        Some(y) => {
+            let utc = |y| {
+                jst.with_ymd_and_hms(y, 1, 1, 0, 0, 0)
+                    .unwrap()
+                    .naive_utc()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            };
+            format!(
+                " where timestamp >= '{}' and timestamp < '{}'",
+                utc(y),
+                utc(y + 1)
+            )
+        }
+        None => String::new(),
+    };
+    let query = format!(
+        "select * from {}{} order by timestamp, execution_id",
+        config.table("my_executions"),
+        range
+    );
+
+    let bq_client = create_bq_client(config).await;
+    let pages = query_all(&bq_client, project_id, query).await.unwrap();
+
+    let mut executions: Vec<MyExecutions> = vec![];
+    let mut seen: HashSet<i64> = HashSet::new();
+    let mut skipped = 0;
+    for mut rs in pages {
+        while rs.next_row() {
+            let e = read_my_executions(&rs);
+            if !seen.insert(e.execution_id) {
+                continue;
+            }
+            // the formats have no place for leverage trading such as BTC_JPY
+            if e.symbol.is_leverage() {
+                skipped += 1;
+                continue;
+            }
+            executions.push(e);
+        }
+    }
+    if skipped > 0 {
+        eprintln!("Skipped {} leverage executions.", skipped);
+    }
+
+    // write to the file, or stdout without it
+    let w: Box<dyn std::io::Write> = match output {
+        Some(path) => Box::new(fs::File::create(path).unwrap()),
+        None => Box::new(std::io::stdout()),
+    };
+    match format {
+        ExportFormat::Cryptact => tax::write_cryptact(w, &executions, config.tz).unwrap(),
+        ExportFormat::Generic => {
+            tax::write_generic(w, &executions, &config.export, config.tz).unwrap()
+        }
+    }
+    if let Some(path) = output {
+        println!("Wrote {} executions to {}", executions.len(), path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(v: &str) -> DateTime<Utc> {
+        parse_gmo_timestamp(v)
+    }
+
+    fn date(v: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(v, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn order_ids_from_plain_list() {
+        let ids = parse_order_ids("\u{feff}101\n102\n\n103\n", None, false).unwrap();
+        assert_eq!(ids, ["101", "102", "103"]);
+    }
+
+    #[test]
+    fn order_ids_from_csv_with_header() {
+        let text = "約定日時,注文ID,銘柄\n2023/06/01,101,BTC\n2023/06/02,\"102\",ETH\n";
+        let ids = parse_order_ids(text, Some("注文ID"), false).unwrap();
+        assert_eq!(ids, ["101", "102"]);
+        let ids = parse_order_ids(text, Some("1"), false).unwrap();
+        assert_eq!(ids, ["101", "102"]);
+        assert_eq!(
+            parse_order_ids(text, Some("orderId"), false).unwrap_err(),
+            "no column orderId"
+        );
+
+        // the header is detected without the column
+        let ids = parse_order_ids("orderId\n101\n102\n", None, false).unwrap();
+        assert_eq!(ids, ["101", "102"]);
+    }
+
+    #[test]
+    fn order_ids_without_header() {
+        let text = "2023/06/01,101\n2023/06/02,102\n";
+        let ids = parse_order_ids(text, Some("1"), true).unwrap();
+        assert_eq!(ids, ["101", "102"]);
+        // the first line is not an ID, but is not the header either
+        let ids = parse_order_ids("a,101\nb,102\n", Some("1"), true).unwrap();
+        assert_eq!(ids, ["101", "102"]);
+        // the header is detected by the column given by index
+        let ids = parse_order_ids(text, Some("1"), false).unwrap();
+        assert_eq!(ids, ["101", "102"]);
+        let ids = parse_order_ids("date,id\n2023/06/01,101\n", Some("1"), false).unwrap();
+        assert_eq!(ids, ["101"]);
+        assert_eq!(
+            parse_order_ids(text, Some("注文ID"), true).unwrap_err(),
+            "column 注文ID needs the header"
+        );
+    }
+
+    #[test]
+    fn order_ids_from_json() {
+        let ids = parse_order_ids(" [101, \"102\"] ", None, false).unwrap();
+        assert_eq!(ids, ["101", "102"]);
+        assert!(parse_order_ids("[101, null]", None, false).is_err());
+    }
+
+    // Write the text into a file in the temporary directory in the encoding.
+    fn write_temp(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    const TRADE_REPORT: &str = "日時,約定ID,注文ID,銘柄名,売買区分,取引区分,約定数量,約定レート,注文手数料,決済損益,入出金額
+2023/06/01 09:00:00,101,201,BTC,買,,0.01,\"4,000,000\",0,,
+2023/06/02 10:30,102,202,BTC/JPY,売,決済,0.1,\"4,100,000\",-5,\"1,000\",
+2023/06/03 12:00:00,,,JPY,,,,,,,\"10,000\"
+";
+
+    #[test]
+    fn trade_report_in_shift_jis() {
+        let (bytes, _, _) = SHIFT_JIS.encode(TRADE_REPORT);
+        let path = write_temp("trade_report_sjis.csv", &bytes);
+        let executions = read_trade_report(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // the deposit has no execution ID
+        assert_eq!(executions.len(), 2);
+        let e = &executions[0];
+        assert_eq!((e.execution_id, e.order_id), (101, 201));
+        assert_eq!(e.symbol.as_str(), "BTC");
+        assert_eq!((e.side, e.settle_type), (Side::Buy, SettleType::Open));
+        assert_eq!(e.price, Decimal::from(4_000_000));
+        assert_eq!(e.loss_gain, Decimal::ZERO);
+        assert_eq!(e.timestamp, utc("2023-06-01T00:00:00Z"));
+
+        let e = &executions[1];
+        assert_eq!(e.symbol.as_str(), "BTC_JPY");
+        assert_eq!((e.side, e.settle_type), (Side::Sell, SettleType::Close));
+        assert_eq!(e.size, "0.1".parse::<Decimal>().unwrap());
+        assert_eq!(e.fee, Decimal::from(-5));
+        assert_eq!(e.loss_gain, Decimal::from(1_000));
+        assert_eq!(e.timestamp, utc("2023-06-02T01:30:00Z"));
+    }
+
+    #[test]
+    fn trade_report_in_utf8_with_bom() {
+        let bytes = [b"\xEF\xBB\xBF".as_slice(), TRADE_REPORT.as_bytes()].concat();
+        let path = write_temp("trade_report_bom.csv", &bytes);
+        let executions = read_trade_report(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(executions.len(), 2);
+    }
+
+    #[test]
+    fn trade_report_without_column() {
+        let (bytes, _, _) = SHIFT_JIS.encode("日時,約定ID\n2023/06/01 09:00:00,101\n");
+        let path = write_temp("trade_report_column.csv", &bytes);
+        let result = read_trade_report(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err(), "no column 注文ID");
+    }
+
+    #[test]
+    fn bq_timestamp_in_epoch_seconds() {
+        assert_eq!(
+            parse_bq_timestamp("1.552961706059E9"),
+            utc("2019-03-19T02:15:06.059Z")
+        );
+        assert_eq!(
+            parse_bq_timestamp("1552961706.000123"),
+            utc("2019-03-19T02:15:06.000123Z")
+        );
+        assert_eq!(parse_bq_timestamp("0"), utc("1970-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn bq_datetime_is_read_as_utc() {
+        assert_eq!(
+            parse_bq_timestamp("2019-03-19T02:15:06"),
+            utc("2019-03-19T02:15:06Z")
+        );
+        assert_eq!(
+            parse_bq_timestamp("2019-03-19T02:15:06.059"),
+            utc("2019-03-19T02:15:06.059Z")
+        );
+    }
+
+    #[test]
+    fn kline_day_starts_at_six_in_jst() {
+        // 2023-06-02 05:59 JST still belongs to the day of 2023-06-01
+        assert_eq!(kline_date(utc("2023-06-01T20:59:00Z")), date("2023-06-01"));
+        assert_eq!(kline_date(utc("2023-06-01T21:00:00Z")), date("2023-06-02"));
+        assert_eq!(
+            kline_day_start(date("2023-06-02")),
+            utc("2023-06-01T21:00:00Z")
+        );
+    }
+
+    #[test]
+    fn kline_dates_by_day_or_year() {
+        assert_eq!(
+            kline_dates("1min", date("2023-12-31"), date("2024-01-02")),
+            vec!["20231231", "20240101", "20240102"]
+        );
+        assert_eq!(
+            kline_dates("1day", date("2023-12-31"), date("2024-01-02")),
+            vec!["2023", "2024"]
+        );
+    }
+
+    #[test]
+    fn orderbook_depth_within_range() {
+        let d = |v: &str| v.parse::<Decimal>().unwrap();
+        let asks = vec![
+            (d("1001"), d("1")),
+            (d("1005"), d("2")),
+            (d("1015"), d("4")),
+        ];
+        let bids = vec![(d("999"), d("1")), (d("994"), d("2")), (d("980"), d("4"))];
+        let m = orderbook_metrics(utc("2023-06-01T00:00:00Z"), "BTC", &asks, &bids).unwrap();
+        assert_eq!(m.best_ask, d("1001"));
+        assert_eq!(m.best_bid, d("999"));
+        assert_eq!(m.spread, d("2"));
+        assert_eq!(m.mid_price, d("1000"));
+        // 0.5% is 995 - 1005 and 1% is 990 - 1010, both inclusive
+        assert_eq!(m.ask_depth_0_5, d("3"));
+        assert_eq!(m.bid_depth_0_5, d("1"));
+        assert_eq!(m.ask_depth_1, d("3"));
+        assert_eq!(m.bid_depth_1, d("3"));
+
+        assert!(orderbook_metrics(m.timestamp, "BTC", &asks, &[]).is_none());
+    }
+
+    #[test]
+    fn kline_closed_after_interval() {
+        let open = utc("2023-06-01T00:00:00Z");
+        assert!(!kline_closed("1hour", open, utc("2023-06-01T00:59:59Z")));
+        assert!(kline_closed("1hour", open, utc("2023-06-01T01:00:00Z")));
+        assert!(!kline_closed("1month", open, utc("2023-06-30T00:00:00Z")));
+    }
+}
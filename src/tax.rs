@@ -0,0 +1,606 @@
+use {
+    crate::{
+        config::ExportConfig,
+        gmo::{Side, Symbol},
+        models::MyExecutions,
+        position::{CostMethod, Fill, PositionCalculator},
+    },
+    chrono::{DateTime, Datelike, FixedOffset, Utc},
+    chrono_tz::Tz,
+    rust_decimal::Decimal,
+    std::{collections::BTreeMap, fmt::Write as _, io, str::FromStr},
+};
+
+// Columns of the calculation worksheet of crypto assets (暗号資産の計算書) by the NTA.
+const REPORT_HEADERS: [&str; 12] = [
+    "銘柄",
+    "年始残高(数量)",
+    "年始残高(金額)",
+    "購入(数量)",
+    "購入(金額)",
+    "売却(数量)",
+    "売却(金額)",
+    "年末残高(数量)",
+    "年末残高(金額)",
+    "売却原価",
+    "必要経費",
+    "所得金額",
+];
+
+// Columns of the custom file of Cryptact.
+const CRYPTACT_HEADERS: [&str; 10] = [
+    "Timestamp",
+    "Action",
+    "Source",
+    "Base",
+    "Volume",
+    "Price",
+    "Counter",
+    "Fee",
+    "FeeCcy",
+    "Comment",
+];
+
+// Fields of an execution which can be mapped to the columns of the generic format.
+pub const EXPORT_FIELDS: [&str; 11] = [
+    "execution_id",
+    "order_id",
+    "timestamp",
+    "symbol",
+    "side",
+    "settle_type",
+    "size",
+    "price",
+    "amount",
+    "fee",
+    "loss_gain",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Cryptact,
+    Generic,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cryptact" => Ok(ExportFormat::Cryptact),
+            "generic" => Ok(ExportFormat::Generic),
+            _ => Err(format!("unknown format: {} (cryptact or generic)", s)),
+        }
+    }
+}
+
+// An event which changes the holding of a symbol.
+#[derive(Debug, Clone)]
+pub enum TaxEvent {
+    Trade(Fill),
+    Deposit(DateTime<Utc>, Decimal),
+    Withdrawal(DateTime<Utc>, Decimal),
+}
+
+impl TaxEvent {
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            TaxEvent::Trade(f) => f.timestamp,
+            TaxEvent::Deposit(t, _) | TaxEvent::Withdrawal(t, _) => *t,
+        }
+    }
+}
+
+// Yearly summary of a symbol. Amounts are in JPY, and fees of purchases are included in the
+// purchase amount while fees of sales are the necessary expenses.
+#[derive(Debug, Default)]
+pub struct YearSummary {
+    pub symbol: String,
+    pub opening_size: Decimal,
+    pub opening_cost: Decimal,
+    pub purchase_size: Decimal,
+    pub purchase_amount: Decimal,
+    pub sale_size: Decimal,
+    pub sale_amount: Decimal,
+    pub closing_size: Decimal,
+    pub closing_cost: Decimal,
+    pub cost_of_sales: Decimal,
+    pub fees: Decimal,
+    pub deposit_size: Decimal,
+    pub withdrawal_size: Decimal,
+}
+
+impl YearSummary {
+    pub fn income(&self) -> Decimal {
+        self.sale_amount - self.cost_of_sales - self.fees
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.symbol.clone(),
+            format_size(self.opening_size),
+            format_jpy(self.opening_cost),
+            format_size(self.purchase_size),
+            format_jpy(self.purchase_amount),
+            format_size(self.sale_size),
+            format_jpy(self.sale_amount),
+            format_size(self.closing_size),
+            format_jpy(self.closing_cost),
+            format_jpy(self.cost_of_sales),
+            format_jpy(self.fees),
+            format_jpy(self.income()),
+        ]
+    }
+}
+
+// Yearly total of the settlements of a leverage symbol. It is miscellaneous income (雑所得) like
+// spot trading, but is not part of the worksheet.
+#[derive(Debug, Default)]
+pub struct LeverageSummary {
+    pub symbol: String,
+    pub loss_gain: Decimal,
+    pub fee: Decimal,
+}
+
+impl LeverageSummary {
+    pub fn income(&self) -> Decimal {
+        self.loss_gain - self.fee
+    }
+}
+
+// Summarize the events of each symbol in the year (JST). The events must be in time order and
+// include every event before the year, from which the opening balance is calculated.
+pub fn summarize(
+    year: i32,
+    method: CostMethod,
+    events: &BTreeMap<String, Vec<TaxEvent>>,
+) -> Vec<YearSummary> {
+    let jst = FixedOffset::east_opt(9 * 60 * 60).unwrap();
+    let mut summaries = vec![];
+    for (symbol, events) in events {
+        let mut calc = PositionCalculator::for_symbol(&Symbol::new(symbol), method);
+        let mut summary = YearSummary {
+            symbol: symbol.clone(),
+            ..Default::default()
+        };
+        let mut opened = false;
+        let mut withdrawn_cost = Decimal::ZERO;
+        for e in events {
+            let y = e.timestamp().with_timezone(&jst).year();
+            if y > year {
+                break;
+            }
+            if y == year && !opened {
+                (summary.opening_size, summary.opening_cost) = calc.holding();
+                opened = true;
+            }
+            match e {
+                TaxEvent::Trade(fill) => {
+                    calc.apply(fill);
+                    if y < year {
+                        continue;
+                    }
+                    if fill.side == Side::Buy {
+                        summary.purchase_size += fill.size;
+                        summary.purchase_amount += fill.price * fill.size + fill.fee;
+                    } else {
+                        summary.sale_size += fill.size;
+                        summary.sale_amount += fill.price * fill.size;
+                        summary.fees += fill.fee;
+                    }
+                }
+                TaxEvent::Deposit(t, size) => {
+                    calc.deposit(*t, *size);
+                    if y == year {
+                        summary.deposit_size += size;
+                    }
+                }
+                TaxEvent::Withdrawal(t, size) => {
+                    let cost = calc.withdraw(*t, *size);
+                    if y == year {
+                        summary.withdrawal_size += size;
+                        withdrawn_cost += cost;
+                    }
+                }
+            }
+        }
+        if !opened {
+            (summary.opening_size, summary.opening_cost) = calc.holding();
+        }
+        (summary.closing_size, summary.closing_cost) = calc.holding();
+
+        // by total, withdrawals are valued at the average of the whole year, even when nothing is
+        // held at the end of it
+        if method == CostMethod::Total {
+            withdrawn_cost = summary.withdrawal_size * calc.year_average_price();
+        }
+        summary.cost_of_sales =
+            summary.opening_cost + summary.purchase_amount - summary.closing_cost - withdrawn_cost;
+
+        // symbols which had nothing in the year are omitted
+        if summary.opening_size > Decimal::ZERO
+            || summary.purchase_size > Decimal::ZERO
+            || summary.sale_size > Decimal::ZERO
+            || summary.closing_size > Decimal::ZERO
+        {
+            summaries.push(summary);
+        }
+    }
+    summaries
+}
+
+pub fn write_csv<W: io::Write>(w: W, summaries: &[YearSummary]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(w);
+    writer.write_record(REPORT_HEADERS)?;
+    for s in summaries {
+        writer.write_record(s.row())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn to_markdown(
+    year: i32,
+    method: CostMethod,
+    summaries: &[YearSummary],
+    leverage: &[LeverageSummary],
+) -> String {
+    let method_name = match method {
+        CostMethod::Moving => "移動平均法",
+        CostMethod::Total => "総平均法",
+        CostMethod::Fifo => "先入先出法",
+    };
+    let mut md = String::new();
+    writeln!(md, "# {}年分 暗号資産の計算書（{}）", year, method_name).unwrap();
+    writeln!(md).unwrap();
+    writeln!(md, "| {} |", REPORT_HEADERS.join(" | ")).unwrap();
+    writeln!(md, "|{}", "---|".repeat(REPORT_HEADERS.len())).unwrap();
+    for s in summaries {
+        writeln!(md, "| {} |", s.row().join(" | ")).unwrap();
+    }
+    let total: Decimal = summaries.iter().map(|s| s.income()).sum();
+    writeln!(md).unwrap();
+    writeln!(md, "所得金額の合計: {} 円", format_jpy(total)).unwrap();
+
+    // transfers are not part of the worksheet, but change the balances
+    let transfers: Vec<&YearSummary> = summaries
+        .iter()
+        .filter(|s| s.deposit_size > Decimal::ZERO || s.withdrawal_size > Decimal::ZERO)
+        .collect();
+    if !transfers.is_empty() {
+        writeln!(md).unwrap();
+        writeln!(md, "## 入出庫").unwrap();
+        writeln!(md).unwrap();
+        for s in transfers {
+            writeln!(
+                md,
+                "- {}: 入庫 {}, 出庫 {}",
+                s.symbol,
+                format_size(s.deposit_size),
+                format_size(s.withdrawal_size)
+            )
+            .unwrap();
+        }
+        writeln!(md).unwrap();
+        writeln!(
+            md,
+            "入庫は取得価額 0 円、出庫は平均取得価額で払い出したものとして計算しています。"
+        )
+        .unwrap();
+    }
+
+    if !leverage.is_empty() {
+        writeln!(md).unwrap();
+        writeln!(md, "## レバレッジ取引").unwrap();
+        writeln!(md).unwrap();
+        writeln!(md, "| 銘柄 | 決済損益 | 手数料 | 所得金額 |").unwrap();
+        writeln!(md, "|---|---|---|---|").unwrap();
+        for l in leverage {
+            writeln!(
+                md,
+                "| {} | {} | {} | {} |",
+                l.symbol,
+                format_jpy(l.loss_gain),
+                format_jpy(l.fee),
+                format_jpy(l.income())
+            )
+            .unwrap();
+        }
+        writeln!(md).unwrap();
+        writeln!(
+            md,
+            "レバレッジ取引の損益は計算書に含まれませんが、雑所得として合算が必要です。"
+        )
+        .unwrap();
+    }
+    md
+}
+
+fn format_size(v: Decimal) -> String {
+    v.round_dp(8).normalize().to_string()
+}
+
+// Amounts in the worksheet are in yen, and fractions are truncated.
+fn format_jpy(v: Decimal) -> String {
+    v.trunc().to_string()
+}
+
+// Write spot executions in the custom file format of Cryptact. Timestamps are in the time zone,
+// and prices and fees are in JPY.
+pub fn write_cryptact<W: io::Write>(
+    w: W,
+    executions: &[MyExecutions],
+    tz: Tz,
+) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(w);
+    writer.write_record(CRYPTACT_HEADERS)?;
+    for e in executions {
+        writer.write_record([
+            format_time(&e.timestamp, tz, "%Y/%m/%d %H:%M:%S"),
+            e.side.to_string(),
+            String::from("GMO"),
+            e.symbol.base().to_string(),
+            e.size.to_string(),
+            e.price.to_string(),
+            String::from("JPY"),
+            e.fee.to_string(),
+            String::from("JPY"),
+            e.execution_id.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Write executions with the columns of the configuration. Timestamps are in the time zone.
+pub fn write_generic<W: io::Write>(
+    w: W,
+    executions: &[MyExecutions],
+    export: &ExportConfig,
+    tz: Tz,
+) -> Result<(), csv::Error> {
+    let columns: Vec<(&str, &str)> = if export.columns.is_empty() {
+        EXPORT_FIELDS.iter().map(|f| (*f, *f)).collect()
+    } else {
+        export
+            .columns
+            .iter()
+            .map(|c| (c.header.as_str(), c.field.as_str()))
+            .collect()
+    };
+
+    let mut writer = csv::Writer::from_writer(w);
+    writer.write_record(columns.iter().map(|(header, _)| header))?;
+    for e in executions {
+        writer.write_record(
+            columns
+                .iter()
+                .map(|(_, field)| field_value(e, field, tz, &export.timestamp_format)),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn field_value(e: &MyExecutions, field: &str, tz: Tz, timestamp_format: &str) -> String {
+    match field {
+        "execution_id" => e.execution_id.to_string(),
+        "order_id" => e.order_id.to_string(),
+        "timestamp" => format_time(&e.timestamp, tz, timestamp_format),
+        "symbol" => e.symbol.to_string(),
+        "side" => e.side.to_string(),
+        "settle_type" => e.settle_type.to_string(),
+        "size" => e.size.to_string(),
+        "price" => e.price.to_string(),
+        "amount" => format_jpy(e.price * e.size),
+        "fee" => e.fee.to_string(),
+        "loss_gain" => e.loss_gain.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn format_time(timestamp: &DateTime<Utc>, tz: Tz, format: &str) -> String {
+    timestamp.with_timezone(&tz).format(format).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{config::ExportColumn, gmo::SettleType},
+        chrono::TimeZone,
+    };
+
+    fn d(v: &str) -> Decimal {
+        v.parse().unwrap()
+    }
+
+    fn jst(y: i32, m: u32, day: u32, h: u32) -> DateTime<Utc> {
+        FixedOffset::east_opt(9 * 60 * 60)
+            .unwrap()
+            .with_ymd_and_hms(y, m, day, h, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn trade(timestamp: DateTime<Utc>, side: Side, size: &str, price: &str, fee: &str) -> TaxEvent {
+        TaxEvent::Trade(Fill {
+            timestamp,
+            side,
+            settle_type: SettleType::Open,
+            size: d(size),
+            price: d(price),
+            fee: d(fee),
+        })
+    }
+
+    #[test]
+    fn summary_by_moving_average() {
+        let mut events = BTreeMap::new();
+        events.insert(
+            String::from("BTC"),
+            vec![
+                trade(jst(2022, 12, 31, 23), Side::Buy, "1", "100", "0"),
+                // the year is in JST
+                trade(jst(2023, 1, 1, 0), Side::Buy, "1", "200", "0"),
+                trade(jst(2023, 6, 1, 0), Side::Sell, "1", "300", "1"),
+                trade(jst(2024, 1, 1, 0), Side::Sell, "1", "400", "0"),
+            ],
+        );
+        // nothing happened to ETH in the year
+        events.insert(
+            String::from("ETH"),
+            vec![
+                trade(jst(2022, 1, 1, 0), Side::Buy, "1", "10", "0"),
+                trade(jst(2022, 2, 1, 0), Side::Sell, "1", "20", "0"),
+            ],
+        );
+
+        let summaries = summarize(2023, CostMethod::Moving, &events);
+        assert_eq!(summaries.len(), 1);
+        let s = &summaries[0];
+        assert_eq!(s.symbol, "BTC");
+        assert_eq!((s.opening_size, s.opening_cost), (d("1"), d("100")));
+        assert_eq!((s.purchase_size, s.purchase_amount), (d("1"), d("200")));
+        assert_eq!((s.sale_size, s.sale_amount), (d("1"), d("300")));
+        assert_eq!((s.closing_size, s.closing_cost), (d("1"), d("150")));
+        assert_eq!(s.cost_of_sales, d("150"));
+        assert_eq!(s.fees, d("1"));
+        assert_eq!(s.income(), d("149"));
+    }
+
+    #[test]
+    fn summary_by_total_average() {
+        let mut events = BTreeMap::new();
+        events.insert(
+            String::from("BTC"),
+            vec![
+                trade(jst(2023, 1, 1, 0), Side::Buy, "1", "100", "0"),
+                trade(jst(2023, 2, 1, 0), Side::Sell, "1", "300", "0"),
+                // a purchase after the sale changes the average of the year
+                trade(jst(2023, 3, 1, 0), Side::Buy, "1", "200", "0"),
+            ],
+        );
+
+        let summaries = summarize(2023, CostMethod::Total, &events);
+        let s = &summaries[0];
+        assert_eq!((s.closing_size, s.closing_cost), (d("1"), d("150")));
+        assert_eq!(s.cost_of_sales, d("150"));
+        assert_eq!(s.income(), d("150"));
+    }
+
+    #[test]
+    fn withdrawal_of_whole_holding_by_total_average() {
+        let mut events = BTreeMap::new();
+        events.insert(
+            String::from("BTC"),
+            vec![
+                trade(jst(2023, 1, 1, 0), Side::Buy, "1", "100", "0"),
+                TaxEvent::Withdrawal(jst(2023, 2, 1, 0), d("1")),
+            ],
+        );
+
+        let summaries = summarize(2023, CostMethod::Total, &events);
+        let s = &summaries[0];
+        assert_eq!(
+            (s.closing_size, s.closing_cost),
+            (Decimal::ZERO, Decimal::ZERO)
+        );
+        // nothing was sold, so there is no loss
+        assert_eq!(s.cost_of_sales, Decimal::ZERO);
+        assert_eq!(s.income(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn deposit_is_acquired_at_zero_cost() {
+        let mut events = BTreeMap::new();
+        events.insert(
+            String::from("BTC"),
+            vec![
+                TaxEvent::Deposit(jst(2023, 1, 1, 0), d("1")),
+                trade(jst(2023, 2, 1, 0), Side::Buy, "1", "100", "0"),
+                TaxEvent::Withdrawal(jst(2023, 3, 1, 0), d("1")),
+            ],
+        );
+
+        let summaries = summarize(2023, CostMethod::Moving, &events);
+        let s = &summaries[0];
+        assert_eq!((s.deposit_size, s.withdrawal_size), (d("1"), d("1")));
+        assert_eq!((s.closing_size, s.closing_cost), (d("1"), d("50")));
+        // the withdrawal is not a sale
+        assert_eq!(s.cost_of_sales, Decimal::ZERO);
+        assert_eq!(s.income(), Decimal::ZERO);
+    }
+
+    fn execution() -> MyExecutions {
+        MyExecutions {
+            execution_id: 1,
+            order_id: 2,
+            symbol: Symbol::new("BTC"),
+            side: Side::Buy,
+            settle_type: SettleType::Open,
+            size: d("0.5"),
+            price: d("10000003"),
+            loss_gain: Decimal::ZERO,
+            fee: d("3"),
+            timestamp: Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn generic_with_configured_columns() {
+        let export = ExportConfig {
+            timestamp_format: String::from("%Y/%m/%d %H:%M"),
+            columns: [
+                ("日時", "timestamp"),
+                ("数量", "size"),
+                ("金額", "amount"),
+                ("備考", "unknown"),
+            ]
+            .iter()
+            .map(|(header, field)| ExportColumn {
+                header: header.to_string(),
+                field: field.to_string(),
+            })
+            .collect(),
+        };
+        let mut out = vec![];
+        write_generic(&mut out, &[execution()], &export, chrono_tz::Asia::Tokyo).unwrap();
+        // the amount is truncated to yen, and unknown fields are empty
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "日時,数量,金額,備考\n2023/06/01 09:00,0.5,5000001,\n"
+        );
+    }
+
+    #[test]
+    fn generic_with_every_field_by_default() {
+        let mut out = vec![];
+        write_generic(
+            &mut out,
+            &[execution()],
+            &ExportConfig::default(),
+            chrono_tz::UTC,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], EXPORT_FIELDS.join(","));
+        assert_eq!(
+            lines[1],
+            "1,2,2023-06-01 00:00:00,BTC,BUY,OPEN,0.5,10000003,5000001,3,0"
+        );
+    }
+
+    #[test]
+    fn markdown_with_leverage_section() {
+        let leverage = [LeverageSummary {
+            symbol: String::from("BTC_JPY"),
+            loss_gain: d("1000.5"),
+            fee: d("-10"),
+        }];
+        let md = to_markdown(2023, CostMethod::Moving, &[], &leverage);
+        assert!(md.contains("所得金額の合計: 0 円"));
+        assert!(md.contains("| BTC_JPY | 1000 | -10 | 1010 |"));
+    }
+}
@@ -0,0 +1,553 @@
+use {
+    crate::gmo::{
+        Execution, GmoClient, Orderbook, SettleType, Side, Symbol, TickerData, Trade,
+        WsAuthResponse,
+    },
+    chrono::{DateTime, Utc},
+    futures_util::{SinkExt, Stream, StreamExt},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashSet,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    },
+    tokio::{
+        net::TcpStream,
+        sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        time::{interval_at, sleep, sleep_until, Instant},
+    },
+    tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream},
+};
+
+const PUBLIC_WS_URL: &str = "wss://api.coin.z.com/ws/public/v1";
+const PRIVATE_WS_URL: &str = "wss://api.coin.z.com/ws/private/v1";
+
+// GMO accepts one subscribe/unsubscribe command per second.
+const COMMAND_INTERVAL: Duration = Duration::from_secs(1);
+
+// Wait time before reconnecting after the connection is lost.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Access tokens expire in 60 minutes unless they are extended.
+const TOKEN_EXTEND_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PublicChannel {
+    Ticker,
+    Orderbooks,
+    Trades,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+#[serde(tag = "channel", rename_all = "lowercase")]
+pub enum PublicEvent {
+    Ticker(TickerData),
+    Orderbooks(Orderbook),
+    Trades(Trade),
+}
+
+#[derive(Debug, Serialize)]
+struct PublicCommand<'a> {
+    command: &'a str,
+    channel: PublicChannel,
+    symbol: &'a str,
+}
+
+#[derive(Debug)]
+enum PublicRequest {
+    Subscribe(PublicChannel, String),
+    Unsubscribe(PublicChannel, String),
+}
+
+// Client of the public WebSocket API. Events of the subscribed channels are read as a Stream.
+pub struct PublicWsClient {
+    requests: UnboundedSender<PublicRequest>,
+    events: UnboundedReceiver<PublicEvent>,
+}
+
+impl PublicWsClient {
+    // Connect in background. The connection is re-established and the subscriptions are restored
+    // whenever it is lost, until the client is dropped.
+    pub fn connect() -> Self {
+        let (req_tx, req_rx) = unbounded_channel();
+        let (ev_tx, ev_rx) = unbounded_channel();
+        tokio::spawn(run_public(req_rx, ev_tx));
+        PublicWsClient {
+            requests: req_tx,
+            events: ev_rx,
+        }
+    }
+
+    pub fn subscribe(&self, channel: PublicChannel, symbol: &str) {
+        self.requests
+            .send(PublicRequest::Subscribe(channel, symbol.to_string()))
+            .ok();
+    }
+
+    #[allow(dead_code)]
+    pub fn unsubscribe(&self, channel: PublicChannel, symbol: &str) {
+        self.requests
+            .send(PublicRequest::Unsubscribe(channel, symbol.to_string()))
+            .ok();
+    }
+}
+
+impl Stream for PublicWsClient {
+    type Item = PublicEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+async fn run_public(
+    mut requests: UnboundedReceiver<PublicRequest>,
+    events: UnboundedSender<PublicEvent>,
+) {
+    let mut subscriptions: HashSet<(PublicChannel, String)> = HashSet::new();
+    let mut throttle = Throttle::new();
+
+    loop {
+        let mut ws = match connect_async(PUBLIC_WS_URL).await {
+            Ok((ws, _)) => ws,
+            Err(e) => {
+                println!("Failed to connect to {} => {:?}", PUBLIC_WS_URL, e);
+                sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        println!("Connected to {}", PUBLIC_WS_URL);
+
+        // restore subscriptions of the previous connection
+        let mut connected = true;
+        for (channel, symbol) in &subscriptions {
+            let cmd = PublicCommand {
+                command: "subscribe",
+                channel: *channel,
+                symbol,
+            };
+            if !send_command(&mut ws, &mut throttle, &cmd).await {
+                connected = false;
+                break;
+            }
+        }
+
+        while connected {
+            tokio::select! {
+                req = requests.recv() => {
+                    let (command, channel, symbol) = match req {
+                        Some(PublicRequest::Subscribe(channel, symbol)) => {
+                            if !subscriptions.insert((channel, symbol.clone())) {
+                                continue;
+                            }
+                            ("subscribe", channel, symbol)
+                        }
+                        Some(PublicRequest::Unsubscribe(channel, symbol)) => {
+                            if !subscriptions.remove(&(channel, symbol.clone())) {
+                                continue;
+                            }
+                            ("unsubscribe", channel, symbol)
+                        }
+                        // the client has been dropped
+                        None => {
+                            ws.close(None).await.ok();
+                            return;
+                        }
+                    };
+                    let cmd = PublicCommand { command, channel, symbol: &symbol };
+                    connected = send_command(&mut ws, &mut throttle, &cmd).await;
+                }
+                msg = ws.next() => {
+                    match read_text(msg) {
+                        Ok(Some(text)) => match serde_json::from_str::<PublicEvent>(&text) {
+                            Ok(event) => {
+                                if events.send(event).is_err() {
+                                    return;
+                                }
+                            }
+                            Err(_) => println!("Unexpected message: {}", text),
+                        },
+                        Ok(None) => {}
+                        Err(_) => connected = false,
+                    }
+                }
+            }
+        }
+
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum PrivateChannel {
+    #[serde(rename = "executionEvents")]
+    Execution,
+    #[serde(rename = "orderEvents")]
+    Order,
+    #[serde(rename = "positionEvents")]
+    Position,
+    #[serde(rename = "positionSummaryEvents")]
+    PositionSummary,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+#[serde(tag = "channel")]
+pub enum PrivateEvent {
+    #[serde(rename = "executionEvents")]
+    Execution(ExecutionEvent),
+    #[serde(rename = "orderEvents")]
+    Order(OrderEvent),
+    #[serde(rename = "positionEvents")]
+    Position(PositionEvent),
+    #[serde(rename = "positionSummaryEvents")]
+    PositionSummary(PositionSummaryEvent),
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct ExecutionEvent {
+    #[serde(rename(deserialize = "orderId"))]
+    pub order_id: i64,
+    #[serde(rename(deserialize = "executionId"))]
+    pub execution_id: i64,
+    pub symbol: Symbol,
+    #[serde(rename(deserialize = "settleType"))]
+    pub settle_type: SettleType,
+    #[serde(rename(deserialize = "executionType"))]
+    pub execution_type: String,
+    pub side: Side,
+    #[serde(rename(deserialize = "executionPrice"))]
+    pub execution_price: String,
+    #[serde(rename(deserialize = "executionSize"))]
+    pub execution_size: String,
+    #[serde(rename(deserialize = "positionId"))]
+    pub position_id: Option<i64>,
+    #[serde(rename(deserialize = "orderTimestamp"))]
+    pub order_timestamp: String,
+    #[serde(rename(deserialize = "executionTimestamp"))]
+    pub execution_timestamp: DateTime<Utc>,
+    #[serde(rename(deserialize = "lossGain"))]
+    pub loss_gain: String,
+    pub fee: String,
+    #[serde(rename(deserialize = "orderPrice"))]
+    pub order_price: Option<String>,
+    #[serde(rename(deserialize = "orderSize"))]
+    pub order_size: String,
+    #[serde(rename(deserialize = "orderExecutedSize"))]
+    pub order_executed_size: String,
+    #[serde(rename(deserialize = "timeInForce"))]
+    pub time_in_force: String,
+    #[serde(rename(deserialize = "msgType"))]
+    pub msg_type: String,
+}
+
+impl From<&ExecutionEvent> for Execution {
+    fn from(e: &ExecutionEvent) -> Self {
+        Execution {
+            execution_id: e.execution_id,
+            order_id: e.order_id,
+            symbol: e.symbol.clone(),
+            side: e.side,
+            settle_type: e.settle_type,
+            size: e.execution_size.clone(),
+            price: e.execution_price.clone(),
+            loss_gain: e.loss_gain.clone(),
+            fee: e.fee.clone(),
+            timestamp: e.execution_timestamp,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct OrderEvent {
+    #[serde(rename(deserialize = "orderId"))]
+    pub order_id: i64,
+    pub symbol: String,
+    #[serde(rename(deserialize = "settleType"))]
+    pub settle_type: String,
+    #[serde(rename(deserialize = "executionType"))]
+    pub execution_type: String,
+    pub side: String,
+    #[serde(rename(deserialize = "orderStatus"))]
+    pub order_status: String,
+    #[serde(rename(deserialize = "cancelType"))]
+    pub cancel_type: Option<String>,
+    #[serde(rename(deserialize = "orderTimestamp"))]
+    pub order_timestamp: String,
+    #[serde(rename(deserialize = "orderPrice"))]
+    pub order_price: Option<String>,
+    #[serde(rename(deserialize = "orderSize"))]
+    pub order_size: String,
+    #[serde(rename(deserialize = "orderExecutedSize"))]
+    pub order_executed_size: String,
+    #[serde(rename(deserialize = "losscutPrice"))]
+    pub losscut_price: Option<String>,
+    #[serde(rename(deserialize = "timeInForce"))]
+    pub time_in_force: String,
+    #[serde(rename(deserialize = "msgType"))]
+    pub msg_type: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct PositionEvent {
+    #[serde(rename(deserialize = "positionId"))]
+    pub position_id: i64,
+    pub symbol: String,
+    pub side: String,
+    pub size: String,
+    #[serde(rename(deserialize = "orderdSize"))]
+    pub orderd_size: String,
+    pub price: String,
+    #[serde(rename(deserialize = "lossGain"))]
+    pub loss_gain: String,
+    pub leverage: String,
+    #[serde(rename(deserialize = "losscutPrice"))]
+    pub losscut_price: Option<String>,
+    pub timestamp: String,
+    #[serde(rename(deserialize = "msgType"))]
+    pub msg_type: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+pub struct PositionSummaryEvent {
+    pub symbol: String,
+    pub side: String,
+    #[serde(rename(deserialize = "averagePositionRate"))]
+    pub average_position_rate: String,
+    #[serde(rename(deserialize = "positionLossGain"))]
+    pub position_loss_gain: String,
+    #[serde(rename(deserialize = "sumOrderQuantity"))]
+    pub sum_order_quantity: String,
+    #[serde(rename(deserialize = "sumPositionQuantity"))]
+    pub sum_position_quantity: String,
+    pub timestamp: String,
+    #[serde(rename(deserialize = "msgType"))]
+    pub msg_type: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PrivateCommand<'a> {
+    command: &'a str,
+    channel: PrivateChannel,
+}
+
+#[derive(Debug)]
+enum PrivateRequest {
+    Subscribe(PrivateChannel),
+    Unsubscribe(PrivateChannel),
+}
+
+// Client of the private WebSocket API. Events of the subscribed channels are read as a Stream.
+pub struct PrivateWsClient {
+    requests: UnboundedSender<PrivateRequest>,
+    events: UnboundedReceiver<PrivateEvent>,
+}
+
+impl PrivateWsClient {
+    // Connect in background with an access token obtained by the GMO client. The token is
+    // extended periodically, and the connection is re-established with the subscriptions restored
+    // whenever it is lost, until the client is dropped.
+    pub fn connect(gmo: GmoClient) -> Self {
+        let (req_tx, req_rx) = unbounded_channel();
+        let (ev_tx, ev_rx) = unbounded_channel();
+        tokio::spawn(run_private(gmo, req_rx, ev_tx));
+        PrivateWsClient {
+            requests: req_tx,
+            events: ev_rx,
+        }
+    }
+
+    pub fn subscribe(&self, channel: PrivateChannel) {
+        self.requests.send(PrivateRequest::Subscribe(channel)).ok();
+    }
+
+    #[allow(dead_code)]
+    pub fn unsubscribe(&self, channel: PrivateChannel) {
+        self.requests
+            .send(PrivateRequest::Unsubscribe(channel))
+            .ok();
+    }
+}
+
+impl Stream for PrivateWsClient {
+    type Item = PrivateEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+async fn run_private(
+    gmo: GmoClient,
+    mut requests: UnboundedReceiver<PrivateRequest>,
+    events: UnboundedSender<PrivateEvent>,
+) {
+    let mut subscriptions: HashSet<PrivateChannel> = HashSet::new();
+    let mut throttle = Throttle::new();
+    let mut token: Option<String> = None;
+
+    loop {
+        // reuse the token of the previous connection while it is valid
+        let t = match token.take() {
+            Some(t) => t,
+            None => match gmo.create_ws_auth_token().await {
+                Ok(WsAuthResponse { data: Some(t), .. }) => t,
+                res => {
+                    println!("Failed to create access token => {:?}", res);
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            },
+        };
+
+        let mut ws = match connect_async(format!("{}/{}", PRIVATE_WS_URL, t)).await {
+            Ok((ws, _)) => ws,
+            Err(e) => {
+                // the token may have expired, so create a new one next time
+                println!("Failed to connect to {} => {:?}", PRIVATE_WS_URL, e);
+                sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        println!("Connected to {}", PRIVATE_WS_URL);
+        token = Some(t.clone());
+
+        // restore subscriptions of the previous connection
+        let mut connected = true;
+        for channel in &subscriptions {
+            let cmd = PrivateCommand {
+                command: "subscribe",
+                channel: *channel,
+            };
+            if !send_command(&mut ws, &mut throttle, &cmd).await {
+                connected = false;
+                break;
+            }
+        }
+
+        let mut extension = interval_at(
+            Instant::now() + TOKEN_EXTEND_INTERVAL,
+            TOKEN_EXTEND_INTERVAL,
+        );
+        while connected {
+            tokio::select! {
+                req = requests.recv() => {
+                    let (command, channel) = match req {
+                        Some(PrivateRequest::Subscribe(channel)) => {
+                            if !subscriptions.insert(channel) {
+                                continue;
+                            }
+                            ("subscribe", channel)
+                        }
+                        Some(PrivateRequest::Unsubscribe(channel)) => {
+                            if !subscriptions.remove(&channel) {
+                                continue;
+                            }
+                            ("unsubscribe", channel)
+                        }
+                        // the client has been dropped
+                        None => {
+                            ws.close(None).await.ok();
+                            gmo.delete_ws_auth_token(&t).await.ok();
+                            return;
+                        }
+                    };
+                    let cmd = PrivateCommand { command, channel };
+                    connected = send_command(&mut ws, &mut throttle, &cmd).await;
+                }
+                _ = extension.tick() => {
+                    match gmo.extend_ws_auth_token(&t).await {
+                        Ok(WsAuthResponse { status: 0, .. }) => println!("Extended access token."),
+                        res => {
+                            println!("Failed to extend access token => {:?}", res);
+                            token = None;
+                            connected = false;
+                        }
+                    }
+                }
+                msg = ws.next() => {
+                    match read_text(msg) {
+                        Ok(Some(text)) => match serde_json::from_str::<PrivateEvent>(&text) {
+                            Ok(event) => {
+                                if events.send(event).is_err() {
+                                    ws.close(None).await.ok();
+                                    gmo.delete_ws_auth_token(&t).await.ok();
+                                    return;
+                                }
+                            }
+                            Err(_) => println!("Unexpected message: {}", text),
+                        },
+                        Ok(None) => {}
+                        Err(_) => connected = false,
+                    }
+                }
+            }
+        }
+
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+// Send a command as JSON text. Returns false when the connection is lost.
+async fn send_command<T: Serialize>(ws: &mut WsStream, throttle: &mut Throttle, cmd: &T) -> bool {
+    throttle.wait().await;
+    let text = serde_json::to_string(cmd).unwrap();
+    match ws.send(Message::text(text)).await {
+        Ok(_) => true,
+        Err(e) => {
+            println!("Failed to send command => {:?}", e);
+            false
+        }
+    }
+}
+
+// Take the text out of a received message. Returns Err when the connection is lost.
+fn read_text(
+    msg: Option<Result<Message, tokio_tungstenite::tungstenite::Error>>,
+) -> Result<Option<String>, ()> {
+    match msg {
+        Some(Ok(Message::Text(text))) => Ok(Some(text.to_string())),
+        Some(Ok(Message::Close(frame))) => {
+            println!("WebSocket closed => {:?}", frame);
+            Err(())
+        }
+        Some(Ok(_)) => Ok(None),
+        Some(Err(e)) => {
+            println!("WebSocket error => {:?}", e);
+            Err(())
+        }
+        None => {
+            println!("WebSocket closed");
+            Err(())
+        }
+    }
+}
+
+// Keeps subscribe/unsubscribe commands under the rate limit.
+struct Throttle {
+    next: Instant,
+}
+
+impl Throttle {
+    fn new() -> Self {
+        Throttle {
+            next: Instant::now(),
+        }
+    }
+
+    async fn wait(&mut self) {
+        sleep_until(self.next).await;
+        self.next = Instant::now() + COMMAND_INTERVAL;
+    }
+}